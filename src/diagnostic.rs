@@ -0,0 +1,410 @@
+//! A common shape for the errors every phase reports, so tools can consume them without knowing
+//! about each phase's error type, and the renderer that shows them to people
+
+use std::{
+    fmt::Write as _,
+    io::{self, IsTerminal, Write},
+};
+
+use crate::{source_map::SourceMap, span::Span};
+
+/// How many columns a tab advances to a multiple of when rendering source
+const TAB_WIDTH: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// Something wrong with a script, found while lexing, parsing, resolving or running it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Identifies the kind of problem. Codes never change meaning, so tools can match on them.
+    pub code: &'static str,
+    pub message: String,
+    /// The byte offsets of the offending source
+    pub span: Span,
+    /// Other source related to the problem
+    pub labels: Vec<Label>,
+    /// Extra context shown after the source
+    pub notes: Vec<String>,
+    /// Suggestions for fixing the problem, shown after the notes
+    pub help: Vec<String>,
+}
+
+/// A secondary span of a diagnostic, with a message explaining how it's related
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// Whether rendered diagnostics use ANSI colour codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Always,
+    Never,
+}
+
+impl Color {
+    /// Colour only if `stream` is a terminal, and the `NO_COLOR` environment variable isn't set
+    /// to something, as described at <https://no-color.org>
+    pub fn auto(stream: &impl IsTerminal) -> Self {
+        match std::env::var_os("NO_COLOR") {
+            Some(value) if !value.is_empty() => Color::Never,
+            _ if !stream.is_terminal() => Color::Never,
+            _ => Color::Always,
+        }
+    }
+
+    fn paint(self, code: &str, text: &str) -> String {
+        match self {
+            Color::Always => format!("\x1b[{code}m{text}\x1b[0m"),
+            Color::Never => text.to_string(),
+        }
+    }
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: String, span: Span) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            message,
+            span,
+            labels: vec![],
+            notes: vec![],
+            help: vec![],
+        }
+    }
+
+    pub fn with_label(mut self, span: Span, message: String) -> Self {
+        self.labels.push(Label { span, message });
+        self
+    }
+
+    pub fn with_note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    pub fn with_help(mut self, help: String) -> Self {
+        self.help.push(help);
+        self
+    }
+
+    /// Render the diagnostic for people to read, showing the source around the spans it points
+    /// at with each one underlined and labelled
+    pub fn render(
+        &self,
+        source_map: &SourceMap,
+        color: Color,
+        output: &mut dyn Write,
+    ) -> io::Result<()> {
+        let source = source_map.source();
+        let primary = Label {
+            span: self.span,
+            message: self.message.clone(),
+        };
+        let marks = std::iter::once((&primary, true))
+            .chain(self.labels.iter().map(|label| (label, false)))
+            .map(|(label, is_primary)| {
+                let line = source_map.location(label.span.start).line;
+                (line, label, is_primary)
+            })
+            .collect::<Vec<_>>();
+
+        // The line before the primary span is shown too, for context
+        let primary_line = marks[0].0;
+        let mut lines = marks.iter().map(|(line, ..)| *line).collect::<Vec<_>>();
+        if primary_line > 1 {
+            lines.push(primary_line - 1);
+        }
+        lines.sort();
+        lines.dedup();
+        let gutter_width = lines.last().unwrap_or(&1).to_string().len();
+        let blank_gutter = " ".repeat(gutter_width);
+
+        writeln!(
+            output,
+            "\n  {}: {} {}\n",
+            source_map.describe(self.span),
+            color.paint("31", "Error:"),
+            self.message
+        )?;
+
+        let mut previous_line = None;
+        for line in lines {
+            if previous_line.is_some_and(|previous| line > previous + 1) {
+                writeln!(output, " {blank_gutter} ...")?;
+            }
+            previous_line = Some(line);
+
+            let line_span = source_map.line_span(line);
+            let text = line_span.slice(source);
+            writeln!(
+                output,
+                " {} |  {}",
+                color.paint("34", &format!("{line:>gutter_width$}")),
+                expand_tabs(text)
+            )?;
+
+            let mut line_marks = marks
+                .iter()
+                .filter(|(mark_line, ..)| *mark_line == line)
+                .collect::<Vec<_>>();
+            line_marks.sort_by_key(|(_, label, _)| label.span.start);
+            for (_, label, is_primary) in line_marks {
+                // Underlines line up with what's displayed, and stop at the end of the line
+                let start = label.span.start.clamp(line_span.start, line_span.end);
+                let end = label.span.end.clamp(start, line_span.end);
+                let column = display_width(&source[line_span.start..start], 0);
+                let width = display_width(&source[start..end], column).max(1);
+                let (underline, color_code) = if *is_primary {
+                    ("^", "31")
+                } else {
+                    ("-", "34")
+                };
+                writeln!(
+                    output,
+                    " {blank_gutter} |  {}",
+                    color.paint(
+                        color_code,
+                        &format!(
+                            "{}{} {}",
+                            " ".repeat(column),
+                            underline.repeat(width),
+                            label.message
+                        )
+                    )
+                )?;
+            }
+        }
+
+        for note in &self.notes {
+            writeln!(
+                output,
+                " {blank_gutter} = {}: {note}",
+                color.paint("1", "note")
+            )?;
+        }
+        for help in &self.help {
+            writeln!(
+                output,
+                " {blank_gutter} = {}: {help}",
+                color.paint("1", "help")
+            )?;
+        }
+        writeln!(output)
+    }
+
+    /// Describe the diagnostic as a single line JSON object, with the line and column it starts
+    /// and ends at looked up in `source_map`
+    pub fn to_json(&self, source_map: &SourceMap) -> String {
+        let file = match source_map.file_name() {
+            Some(file_name) => json_string(file_name),
+            None => "null".into(),
+        };
+        let labels = self
+            .labels
+            .iter()
+            .map(|label| {
+                format!(
+                    "{{\"message\":{},{}}}",
+                    json_string(&label.message),
+                    json_span(source_map, label.span)
+                )
+            })
+            .collect::<Vec<_>>();
+        let strings = |strings: &[String]| {
+            strings
+                .iter()
+                .map(|string| json_string(string))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        format!(
+            "{{\"severity\":{},\"code\":{},\"message\":{},\"file\":{},{},\
+             \"labels\":[{}],\"notes\":[{}],\"help\":[{}]}}",
+            json_string(self.severity.as_str()),
+            json_string(self.code),
+            json_string(&self.message),
+            file,
+            json_span(source_map, self.span),
+            labels.join(","),
+            strings(&self.notes),
+            strings(&self.help),
+        )
+    }
+}
+
+/// The candidate most like `name`, if one is close enough to be worth suggesting as what was
+/// meant. Names that can't be written in Lox, like `this` and hidden locals, aren't suggested.
+pub(crate) fn closest_name<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .filter(|candidate| {
+            *candidate != name
+                && !matches!(*candidate, "this" | "super")
+                && candidate
+                    .chars()
+                    .next()
+                    .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        })
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        // Break ties by name, so the suggestion doesn't depend on hash map order
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// How many characters have to be inserted, deleted or replaced to turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a_character) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_character) in b.iter().enumerate() {
+            let replace = previous[j] + usize::from(a_character != *b_character);
+            current.push(replace.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The `span`, `start` and `end` fields describing where a span is
+fn json_span(source_map: &SourceMap, span: Span) -> String {
+    let start = source_map.location(span.start);
+    let end = source_map.location(span.end);
+    format!(
+        "\"span\":{{\"start\":{},\"end\":{}}},\
+         \"start\":{{\"line\":{},\"column\":{}}},\"end\":{{\"line\":{},\"column\":{}}}",
+        span.start, span.end, start.line, start.column, end.line, end.column,
+    )
+}
+
+/// How many columns `text` takes up when displayed starting at `column`
+fn display_width(text: &str, column: usize) -> usize {
+    text.chars()
+        .fold(column, |column, character| match character {
+            '\t' => (column / TAB_WIDTH + 1) * TAB_WIDTH,
+            _ => column + 1,
+        })
+        - column
+}
+
+/// Replace tabs with the spaces they'd be displayed as, so underlines line up however wide the
+/// terminal shows tabs
+fn expand_tabs(line: &str) -> String {
+    let mut expanded = String::with_capacity(line.len());
+    for character in line.chars() {
+        match character {
+            '\t' => {
+                let width = display_width("\t", expanded.chars().count());
+                expanded.push_str(&" ".repeat(width));
+            }
+            character => expanded.push(character),
+        }
+    }
+    expanded
+}
+
+/// Quote a string for JSON, escaping the characters JSON strings can't contain
+fn json_string(string: &str) -> String {
+    let mut json = String::with_capacity(string.len() + 2);
+    json.push('"');
+    for character in string.chars() {
+        match character {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            character if character.is_control() => {
+                write!(json, "\\u{:04x}", character as u32).unwrap()
+            }
+            character => json.push(character),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json() {
+        let source = "print \"é\";\nprint -nil;";
+        let diagnostic = Diagnostic::error(
+            "RUNTIME001",
+            "Type Error: \"nil\"\n".into(),
+            Span::new(19, 22),
+        );
+        assert_eq!(
+            diagnostic.to_json(&SourceMap::with_file_name("a\\b.lox", source)),
+            "{\"severity\":\"error\",\"code\":\"RUNTIME001\",\
+             \"message\":\"Type Error: \\\"nil\\\"\\n\",\"file\":\"a\\\\b.lox\",\
+             \"span\":{\"start\":19,\"end\":22},\
+             \"start\":{\"line\":2,\"column\":8},\"end\":{\"line\":2,\"column\":11},\
+             \"labels\":[],\"notes\":[],\"help\":[]}"
+        );
+    }
+
+    #[test]
+    fn closest_names() {
+        let names = ["counter", "count", "this", " index", "print_all"];
+        assert_eq!(closest_name("conter", names), Some("counter"));
+        assert_eq!(closest_name("cont", names), Some("count"));
+        assert_eq!(closest_name("printall", names), Some("print_all"));
+        assert_eq!(closest_name("thus", names), None);
+        assert_eq!(closest_name("index", names), None);
+        assert_eq!(closest_name("total", names), None);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn render() {
+        let source = "fun add(a, b) {\n\treturn a + b;\n}\n\n\n\n\n\n\n\n\tadd(1);";
+        let call = source.rfind("add").unwrap();
+        let diagnostic = Diagnostic::error(
+            "RUNTIME008",
+            "Wrong arity".into(),
+            Span::new(call, call + 6),
+        )
+        .with_label(Span::new(4, 7), "declared here".into())
+        .with_help("pass two arguments".into());
+        let mut output = vec![];
+        diagnostic
+            .render(&SourceMap::new(source), Color::Never, &mut output)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                "\n  11:2: Error: Wrong arity\n\n",
+                "  1 |  fun add(a, b) {\n",
+                "    |      --- declared here\n",
+                "    ...\n",
+                " 10 |  \n",
+                " 11 |      add(1);\n",
+                "    |      ^^^^^^ Wrong arity\n",
+                "    = help: pass two arguments\n\n",
+            )
+        );
+    }
+}
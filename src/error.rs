@@ -1,20 +1,40 @@
-use crate::lexer;
+use std::process::ExitCode;
+
+/// Exit codes from BSD's `sysexits.h`, which shells and other tools understand
+const EX_USAGE: u8 = 64;
+const EX_DATAERR: u8 = 65;
+const EX_SOFTWARE: u8 = 70;
+const EX_IOERR: u8 = 74;
 
 #[derive(Debug)]
 pub enum Error {
     Io(std::io::Error),
     Usage,
-    Lexer(lexer::Error),
+    /// The script had errors found before it ran, which have already been reported
+    Compile,
+    /// The script failed while running, and the error has already been reported
+    Runtime,
+    /// The script called `exit` with a code other than 0
+    Exit(u8),
+    /// Some scripts run by `rusty-lox test` didn't do what they were expected to
+    TestsFailed,
 }
 
-impl From<std::io::Error> for Error {
-    fn from(io_error: std::io::Error) -> Self {
-        Error::Io(io_error)
+impl Error {
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode::from(match self {
+            Error::Io(_) => EX_IOERR,
+            Error::Usage => EX_USAGE,
+            Error::Compile => EX_DATAERR,
+            Error::Runtime => EX_SOFTWARE,
+            Error::Exit(code) => *code,
+            Error::TestsFailed => 1,
+        })
     }
 }
 
-impl From<lexer::Error> for Error {
-    fn from(lexer_error: lexer::Error) -> Self {
-        Error::Lexer(lexer_error)
+impl From<std::io::Error> for Error {
+    fn from(io_error: std::io::Error) -> Self {
+        Error::Io(io_error)
     }
 }
@@ -1,17 +1,23 @@
-use std::rc::Rc;
+use std::{cell::Cell, rc::Rc};
 
-use crate::{lexer::Token, span::Span};
+use crate::{lexer::Token, span::Span, statement::Declaration};
 
 #[derive(Debug)]
 pub enum Expression {
     Assignment(AssignmentExpression),
     Binary(BinaryExpression),
     Call(CallExpression),
+    Function(FunctionExpression),
     Get(GetExpression),
     Grouping(GroupingExpression),
+    Index(IndexExpression),
+    Interpolation(InterpolationExpression),
+    List(ListExpression),
     Literal(LiteralExpression),
     Logical(LogicalExpression),
+    Map(MapExpression),
     Set(SetExpression),
+    SetIndex(SetIndexExpression),
     Super(SuperExpression),
     This(ThisExpression),
     Unary(UnaryExpression),
@@ -29,13 +35,19 @@ impl Expression {
                 binary_expression.right.prettify(source)
             ),
             Expression::Call(_) => todo!(),
+            Expression::Function(_) => todo!(),
             Expression::Get(_) => todo!(),
             Expression::Grouping(group) => {
                 format!("(group {})", group.expression.prettify(source))
             }
+            Expression::Index(_) => todo!(),
+            Expression::Interpolation(_) => todo!(),
+            Expression::List(_) => todo!(),
             Expression::Literal(literal) => literal.prettify(source),
             Expression::Logical(_) => todo!(),
+            Expression::Map(_) => todo!(),
             Expression::Set(_) => todo!(),
+            Expression::SetIndex(_) => todo!(),
             Expression::Super(_) => todo!(),
             Expression::This(_) => todo!(),
             Expression::Unary(unary_expression) => format!(
@@ -49,7 +61,7 @@ impl Expression {
 
     pub fn span(&self) -> Span {
         match self {
-            Expression::Assignment(AssignmentExpression { name, value: _ }) => name.span,
+            Expression::Assignment(AssignmentExpression { name, .. }) => name.span,
             Expression::Binary(BinaryExpression {
                 left,
                 right,
@@ -60,15 +72,53 @@ impl Expression {
                 closing_paren,
                 arguments: _,
             }) => callee.span().combine(closing_paren.span),
+            Expression::Function(FunctionExpression { keyword, .. }) => keyword.span,
             Expression::Get(GetExpression { object, name }) => object.span().combine(name.span),
-            Expression::Grouping(_) => todo!(),
+            Expression::Grouping(GroupingExpression { expression }) => expression.span(),
+            Expression::Index(IndexExpression {
+                object,
+                closing_bracket,
+                ..
+            })
+            | Expression::SetIndex(SetIndexExpression {
+                object,
+                closing_bracket,
+                ..
+            }) => object.span().combine(closing_bracket.span),
+            Expression::Interpolation(InterpolationExpression { parts }) => parts
+                .first()
+                .expect("Interpolated strings have at least two string parts")
+                .span()
+                .combine(parts.last().unwrap().span()),
+            Expression::List(ListExpression {
+                opening_bracket,
+                closing_bracket,
+                ..
+            }) => opening_bracket.span.combine(closing_bracket.span),
             Expression::Literal(literal_expression) => literal_expression.span(),
-            Expression::Logical(_) => todo!(),
-            Expression::Set(_) => todo!(),
-            Expression::Super(_) => todo!(),
-            Expression::This(_) => todo!(),
-            Expression::Unary(_) => todo!(),
-            Expression::Variable(VariableExpression { name }) => name.span,
+            Expression::Logical(LogicalExpression {
+                left,
+                right,
+                operator,
+            }) => left.span().combine(operator.span).combine(right.span()),
+            Expression::Map(MapExpression {
+                opening_brace,
+                closing_brace,
+                ..
+            }) => opening_brace.span.combine(closing_brace.span),
+            Expression::Set(SetExpression {
+                object,
+                name,
+                value: _,
+            }) => object.span().combine(name.span),
+            Expression::Super(SuperExpression {
+                keyword, method, ..
+            }) => keyword.span.combine(method.span),
+            Expression::This(ThisExpression { keyword, .. }) => keyword.span,
+            Expression::Unary(UnaryExpression { operator, right }) => {
+                operator.span.combine(right.span())
+            }
+            Expression::Variable(VariableExpression { name, .. }) => name.span,
         }
     }
 }
@@ -91,8 +141,14 @@ pub fn number_literal_expression(value: Token) -> Rc<Expression> {
     Rc::new(Expression::Literal(LiteralExpression::Number(value)))
 }
 
-pub fn string_literal_expression(value: Token) -> Rc<Expression> {
-    Rc::new(Expression::Literal(LiteralExpression::String_(value)))
+pub fn string_literal_expression(token: Token) -> Rc<Expression> {
+    let value = token
+        .literal
+        .clone()
+        .expect("The lexer gives every string token a literal value");
+    Rc::new(Expression::Literal(LiteralExpression::String_(
+        token, value,
+    )))
 }
 
 pub fn boolean_literal_expression(span: Span, value: bool) -> Rc<Expression> {
@@ -111,6 +167,18 @@ pub fn grouping_expression(expression: Rc<Expression>) -> Rc<Expression> {
 pub struct AssignmentExpression {
     pub name: Token,
     pub value: Rc<Expression>,
+    /// Filled in by the resolver. See [`VariableExpression::depth`]
+    pub depth: Cell<Option<usize>>,
+}
+
+impl AssignmentExpression {
+    pub fn new(name: Token, value: Rc<Expression>) -> Self {
+        Self {
+            name,
+            value,
+            depth: Cell::new(None),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -139,8 +207,18 @@ pub struct CallExpression {
 
 #[derive(Debug)]
 pub struct GetExpression {
-    object: Rc<Expression>,
-    name: Token,
+    pub object: Rc<Expression>,
+    pub name: Token,
+}
+
+/// An anonymous function: `fun (a, b) { ... }`, or an arrow function `(a) => a * 2` whose body
+/// returns the expression after the arrow
+#[derive(Debug)]
+pub struct FunctionExpression {
+    /// The `fun` keyword, or the `=>` of an arrow function
+    pub keyword: Token,
+    pub parameters: Vec<Token>,
+    pub body: Rc<Vec<Declaration>>,
 }
 
 #[derive(Debug)]
@@ -148,9 +226,33 @@ pub struct GroupingExpression {
     pub expression: Rc<Expression>,
 }
 
+/// Reading an element of a list or map: `object[index]`
+#[derive(Debug)]
+pub struct IndexExpression {
+    pub object: Rc<Expression>,
+    pub index: Rc<Expression>,
+    pub closing_bracket: Token,
+}
+
+/// An interpolated string: `"count: ${i}"`. The parts alternate between string literals and
+/// embedded expressions, starting and ending with a string literal.
+#[derive(Debug)]
+pub struct InterpolationExpression {
+    pub parts: Vec<Rc<Expression>>,
+}
+
+/// A list literal: `[1, 2, 3]`
+#[derive(Debug)]
+pub struct ListExpression {
+    pub opening_bracket: Token,
+    pub elements: Vec<Rc<Expression>>,
+    pub closing_bracket: Token,
+}
+
 #[derive(Debug)]
 pub enum LiteralExpression {
-    String_(Token),
+    /// The token and its value with escape sequences decoded
+    String_(Token, String),
     Number(Token),
     Boolean(Span, bool),
     Nil(Span),
@@ -159,7 +261,7 @@ pub enum LiteralExpression {
 impl LiteralExpression {
     fn prettify(&self, source: &str) -> String {
         match self {
-            LiteralExpression::String_(token) => token.span.slice(source).into(),
+            LiteralExpression::String_(token, _) => token.span.slice(source).into(),
             LiteralExpression::Number(token) => token.span.slice(source).into(),
             LiteralExpression::Boolean(_, boolean) => {
                 if *boolean {
@@ -174,7 +276,7 @@ impl LiteralExpression {
 
     pub(crate) fn span(&self) -> Span {
         match self {
-            LiteralExpression::String_(token) => token.span,
+            LiteralExpression::String_(token, _) => token.span,
             LiteralExpression::Number(token) => token.span,
             LiteralExpression::Boolean(span, _) => *span,
             LiteralExpression::Nil(span) => *span,
@@ -189,22 +291,63 @@ pub struct LogicalExpression {
     pub operator: Token,
 }
 
+/// A map literal: `{"key": value}`
+#[derive(Debug)]
+pub struct MapExpression {
+    pub opening_brace: Token,
+    /// Key and value expressions, in source order
+    pub entries: Vec<(Rc<Expression>, Rc<Expression>)>,
+    pub closing_brace: Token,
+}
+
 #[derive(Debug)]
 pub struct SetExpression {
-    object: Rc<Expression>,
-    name: Token,
-    value: Rc<Expression>,
+    pub object: Rc<Expression>,
+    pub name: Token,
+    pub value: Rc<Expression>,
+}
+
+/// Writing an element of a list or map: `object[index] = value`
+#[derive(Debug)]
+pub struct SetIndexExpression {
+    pub object: Rc<Expression>,
+    pub index: Rc<Expression>,
+    pub closing_bracket: Token,
+    pub value: Rc<Expression>,
 }
 
 #[derive(Debug)]
 pub struct SuperExpression {
-    keyword: Token,
-    method: Token,
+    pub keyword: Token,
+    pub method: Token,
+    /// Filled in by the resolver. See [`VariableExpression::depth`]
+    pub depth: Cell<Option<usize>>,
+}
+
+impl SuperExpression {
+    pub fn new(keyword: Token, method: Token) -> Self {
+        Self {
+            keyword,
+            method,
+            depth: Cell::new(None),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct ThisExpression {
-    keyword: Token,
+    pub keyword: Token,
+    /// Filled in by the resolver. See [`VariableExpression::depth`]
+    pub depth: Cell<Option<usize>>,
+}
+
+impl ThisExpression {
+    pub fn new(keyword: Token) -> Self {
+        Self {
+            keyword,
+            depth: Cell::new(None),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -222,4 +365,16 @@ impl UnaryExpression {
 #[derive(Debug)]
 pub struct VariableExpression {
     pub name: Token,
+    /// The number of scopes between where the variable is used and where it was declared.
+    /// Filled in by the resolver, `None` means the variable is a global
+    pub depth: Cell<Option<usize>>,
+}
+
+impl VariableExpression {
+    pub fn new(name: Token) -> Self {
+        Self {
+            name,
+            depth: Cell::new(None),
+        }
+    }
 }
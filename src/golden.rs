@@ -0,0 +1,342 @@
+//! Runs Lox scripts annotated with the output they're expected to produce, in the style of the
+//! [Crafting Interpreters test suite](https://github.com/munificent/craftinginterpreters/tree/master/test).
+//!
+//! The annotations understood are:
+//!
+//! - `// expect: <line>` - the script prints `<line>`. Every line printed has to be expected, in
+//!   order.
+//! - `// expect runtime error: <message>` - the script stops with a runtime error on this line.
+//! - `// Error: <message>` or `// Error at 'x': <message>` - there's a lex, parse or resolve
+//!   error on this line, which with `at 'x'` points at `x`, or past the end of the script with
+//!   `at end`. `// [line N] Error ...` expects the error on line `N` instead.
+
+use std::{
+    cell::RefCell,
+    fmt, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use rusty_lox::{with_stack, Error, Interpreter, SourceMap, Span, Vm};
+
+/// Which implementation to run a script with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    TreeWalker,
+    Vm,
+}
+
+/// An error a script is expected to report
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ExpectedError {
+    pub line: usize,
+    /// What the error points at, as `'x'` or `end`. Any location matches when it's `None`.
+    pub location: Option<String>,
+    pub message: String,
+}
+
+impl ExpectedError {
+    /// Whether an error the script actually reported is this one
+    fn matches(&self, actual: &ExpectedError) -> bool {
+        self.line == actual.line
+            && self.message == actual.message
+            && (self.location.is_none() || self.location == actual.location)
+    }
+}
+
+impl fmt::Display for ExpectedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "at {location}: {}", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Everything a script's annotations say should happen when it's run
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Expectations {
+    pub output: Vec<String>,
+    pub runtime_error: Option<ExpectedError>,
+    pub compile_errors: Vec<ExpectedError>,
+}
+
+impl Expectations {
+    pub fn parse(source: &str) -> Self {
+        let mut expectations = Self::default();
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            if let Some(output) = annotation(line, "// expect:") {
+                expectations.output.push(output.trim().to_string());
+            } else if let Some(message) = annotation(line, "// expect runtime error:") {
+                expectations.runtime_error = Some(ExpectedError {
+                    line: line_number,
+                    location: None,
+                    message: message.trim().to_string(),
+                });
+            } else if let Some(error) = Self::parse_compile_error(line, line_number) {
+                expectations.compile_errors.push(error);
+            }
+        }
+        expectations
+    }
+
+    /// Parse `// Error: message`, `// Error at 'x': message` or `// [line N] Error...`
+    fn parse_compile_error(line: &str, line_number: usize) -> Option<ExpectedError> {
+        let (line_number, error) = match annotation(line, "// [line ") {
+            Some(rest) => {
+                let (line_number, error) = rest.split_once(']')?;
+                (
+                    line_number.parse().ok()?,
+                    error.trim_start().strip_prefix("Error")?,
+                )
+            }
+            None => (line_number, annotation(line, "// Error")?),
+        };
+
+        let (location, message) = match error.strip_prefix(':') {
+            Some(message) => (None, message),
+            None => {
+                let location = error.strip_prefix(" at ")?;
+                // The location can itself be a `:`, so the quotes have to be matched first
+                let (location, message) = match location.strip_prefix('\'') {
+                    Some(quoted) => {
+                        let (token, message) = quoted.split_once("':")?;
+                        (format!("'{token}'"), message)
+                    }
+                    None => {
+                        let (location, message) = location.split_once(':')?;
+                        (location.to_string(), message)
+                    }
+                };
+                (Some(location), message)
+            }
+        };
+
+        Some(ExpectedError {
+            line: line_number,
+            location,
+            message: message.trim().to_string(),
+        })
+    }
+}
+
+/// Run a script with the given backend and check it does what its annotations expect. Returns a
+/// description of every way it didn't.
+pub fn check(source: &str, backend: Backend) -> Result<(), Vec<String>> {
+    // Scripts testing deep recursion need more stack than test threads get
+    with_stack(|| check_on_this_thread(source, backend))
+}
+
+fn check_on_this_thread(source: &str, backend: Backend) -> Result<(), Vec<String>> {
+    let expectations = Expectations::parse(source);
+    let output = SharedBuffer::default();
+
+    let result = rusty_lox::parse(source).and_then(|declarations| {
+        match backend {
+            Backend::TreeWalker => {
+                Interpreter::with_output(output.clone()).interpret(source, declarations)
+            }
+            Backend::Vm => Vm::with_output(output.clone()).interpret(source, &declarations),
+        }
+        .map_err(Error::Runtime)
+    });
+
+    let mut failures = vec![];
+    let source_map = SourceMap::new(source);
+
+    let mut compile_errors = match &result {
+        Err(Error::Syntax(errors)) => errors
+            .iter()
+            .map(|error| actual_error(&source_map, error.span(source), error.message()))
+            .collect(),
+        Err(Error::Resolver(errors)) => errors
+            .iter()
+            .map(|error| actual_error(&source_map, error.span(), error.message()))
+            .collect(),
+        _ => vec![],
+    };
+    compile_errors.sort();
+    let mut expected_compile_errors = expectations.compile_errors.clone();
+    expected_compile_errors.sort();
+    for error in &expected_compile_errors {
+        if !compile_errors.iter().any(|actual| error.matches(actual)) {
+            failures.push(format!(
+                "Missing expected error on line {}: {error}",
+                error.line
+            ));
+        }
+    }
+    for error in &compile_errors {
+        if !expected_compile_errors
+            .iter()
+            .any(|expected| expected.matches(error))
+        {
+            failures.push(format!("Unexpected error on line {}: {error}", error.line));
+        }
+    }
+
+    let runtime_error = match &result {
+        // Exiting ends the script without anything going wrong
+        Err(Error::Runtime(error)) if error.exit_code().is_none() => Some(actual_error(
+            &source_map,
+            error.span(),
+            error.message(source),
+        )),
+        _ => None,
+    };
+    match (&expectations.runtime_error, &runtime_error) {
+        (Some(expected), Some(actual)) if !expected.matches(actual) => failures.push(format!(
+            "Expected runtime error '{}' on line {} and got '{}' on line {}",
+            expected.message, expected.line, actual.message, actual.line
+        )),
+        (Some(expected), None) => failures.push(format!(
+            "Expected runtime error '{}' on line {} but the script didn't fail at runtime",
+            expected.message, expected.line
+        )),
+        (None, Some(actual)) => failures.push(format!(
+            "Unexpected runtime error on line {}: {}",
+            actual.line, actual.message
+        )),
+        _ => {}
+    }
+
+    let output = output.contents();
+    let mut output_lines = output.lines();
+    for expected in &expectations.output {
+        match output_lines.next() {
+            Some(actual) if actual != expected => {
+                failures.push(format!("Expected output '{expected}' and got '{actual}'"))
+            }
+            Some(_) => {}
+            None => failures.push(format!("Missing expected output '{expected}'")),
+        }
+    }
+    for actual in output_lines {
+        failures.push(format!("Got output '{actual}' when none was expected"));
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures)
+    }
+}
+
+/// Find every `.lox` file in a directory and its subdirectories, in a stable order
+pub fn find_scripts(directory: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut scripts = vec![];
+    let mut entries = fs::read_dir(directory)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            scripts.extend(find_scripts(&path)?);
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            scripts.push(path);
+        }
+    }
+    Ok(scripts)
+}
+
+/// The text following `prefix` in a line, if it contains `prefix`
+fn annotation<'a>(line: &'a str, prefix: &str) -> Option<&'a str> {
+    line.find(prefix).map(|index| &line[index + prefix.len()..])
+}
+
+fn actual_error(source_map: &SourceMap, span: Span, message: String) -> ExpectedError {
+    // Only the end of file token runs past the end of the source
+    let location = match source_map.source().get(span.start..span.end) {
+        Some(text) => format!("'{text}'"),
+        None => "end".into(),
+    };
+    ExpectedError {
+        line: source_map.location(span.start).line,
+        location: Some(location),
+        message,
+    }
+}
+
+/// Collects everything a script prints so it can be compared after the interpreter is done
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_expectations() {
+        let source = "print 1; // expect: 1\n\
+                      var a = ; // Error at ';': Expect expression.\n\
+                      // [line 7] Error: Unexpected token\n\
+                      print -nil; // expect runtime error: Operand must be a number.";
+        assert_eq!(
+            Expectations::parse(source),
+            Expectations {
+                output: vec!["1".into()],
+                runtime_error: Some(ExpectedError {
+                    line: 4,
+                    location: None,
+                    message: "Operand must be a number.".into()
+                }),
+                compile_errors: vec![
+                    ExpectedError {
+                        line: 2,
+                        location: Some("';'".into()),
+                        message: "Expect expression.".into()
+                    },
+                    ExpectedError {
+                        line: 7,
+                        location: None,
+                        message: "Unexpected token".into()
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn check_reports_mismatches() {
+        assert_eq!(check("print 1; // expect: 1", Backend::TreeWalker), Ok(()));
+        assert_eq!(
+            check("print 2; // expect: 1", Backend::Vm),
+            Err(vec!["Expected output '1' and got '2'".into()])
+        );
+        assert_eq!(
+            check(
+                "var a = ; // Error at ';': Unexpected token Semicolon",
+                Backend::TreeWalker
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            check(
+                "var a = ; // Error at 'a': Unexpected token Semicolon",
+                Backend::TreeWalker
+            ),
+            Err(vec![
+                "Missing expected error on line 1: at 'a': Unexpected token Semicolon".into(),
+                "Unexpected error on line 1: at ';': Unexpected token Semicolon".into(),
+            ])
+        );
+    }
+}
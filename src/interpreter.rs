@@ -1,41 +1,132 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fmt,
+    io::{self, Write},
+    rc::Rc,
+};
 
 use crate::{
+    diagnostic::closest_name,
     expression::{
-        AssignmentExpression, BinaryExpression, CallExpression, Expression, GroupingExpression,
-        LiteralExpression, LogicalExpression, UnaryExpression, VariableExpression,
+        AssignmentExpression, BinaryExpression, CallExpression, Expression, FunctionExpression,
+        GetExpression, GroupingExpression, IndexExpression, InterpolationExpression,
+        ListExpression, LiteralExpression, LogicalExpression, MapExpression, SetExpression,
+        SetIndexExpression, SuperExpression, ThisExpression, UnaryExpression, VariableExpression,
     },
     lexer::{Token, TokenType},
+    limits::{Budget, Limits},
+    map::Map,
     span::Span,
-    statement::{Declaration, Statement},
+    statement::{CatchClause, Declaration, FunctionDeclaration, Statement},
 };
 use error::Error;
 use value::Value;
 
-use self::{environment::Environment, value::Callable};
+use self::{
+    environment::Environment,
+    error::{ErrorValue, Thrown, TraceFrame},
+    value::{Callable, Class, Instance, NativeFunction},
+};
 
 mod environment;
-mod error;
-mod value;
+pub mod error;
+pub(crate) mod native;
+pub mod value;
+
+/// How many calls deep Lox code can go by default
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+/// A native stack size big enough to evaluate [`DEFAULT_MAX_CALL_DEPTH`] nested calls, even in
+/// debug builds
+pub const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// How much native stack Lox calls can use on threads not started by [`with_stack`]. Threads
+/// Rust spawns get 2 MiB by default, and this leaves room for whatever the host was doing when
+/// it started running Lox.
+const DEFAULT_NATIVE_STACK_BUDGET: usize = 1024 * 1024;
+
+thread_local! {
+    /// How much native stack Lox calls can use on this thread before failing with a stack
+    /// overflow error, whatever the call depth
+    static NATIVE_STACK_BUDGET: Cell<usize> = const { Cell::new(DEFAULT_NATIVE_STACK_BUDGET) };
+}
+
+/// Run `f` on a thread with a [`STACK_SIZE`] stack, so Lox code it runs can recurse as deep as
+/// the default call depth allows
+pub fn with_stack<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, || {
+                // Keep a quarter of the stack for whatever `f` does besides evaluating calls
+                NATIVE_STACK_BUDGET.set(STACK_SIZE / 4 * 3);
+                f()
+            })
+            .expect("Couldn't spawn a thread to run Lox on")
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+/// Roughly where the top of the native stack is
+fn stack_address() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
 
-#[derive(Debug)]
 pub struct Interpreter {
+    globals: Rc<RefCell<Environment>>,
     current_scope: Rc<RefCell<Environment>>,
+    /// The source the code currently being evaluated was parsed from. Functions keep hold of
+    /// the source they were declared in, so they can be called from later calls to
+    /// [`Interpreter::interpret`]
+    source: Rc<str>,
+    /// Where the output of `print` statements is written
+    output: Box<dyn Write>,
+    /// The function calls currently being evaluated, outermost first, for tracing errors
+    call_stack: Vec<TraceFrame>,
+    /// How many calls deep evaluation can go before failing with a stack overflow error
+    max_call_depth: usize,
+    /// The address of the native stack when the current call to [`Interpreter::interpret`]
+    /// started, for measuring how much of it calls have used
+    stack_start: usize,
+    budget: Budget,
+}
+
+impl fmt::Debug for Interpreter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Interpreter")
+            .field("globals", &self.globals)
+            .field("current_scope", &self.current_scope)
+            .field("source", &self.source)
+            .finish_non_exhaustive()
+    }
 }
 
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Why evaluation of a statement stopped before reaching its end
 #[derive(Debug)]
-enum ErrorOrReturn {
+enum Unwind {
     Err(Error),
     Return(Rc<Value>),
+    Break,
+    Continue,
 }
 
 impl Interpreter {
-    fn as_number(&self, value: Rc<Value>) -> Result<f64, Error> {
+    /// The number a value holds, or a type error pointing at `span`, the expression that
+    /// needed a number
+    fn as_number(&self, value: Rc<Value>, span: Span) -> Result<f64, Error> {
         if let Value::Number(_, value) = *value {
             return Ok(value);
         }
 
-        let span = value.span();
         Err(Error::type_error(
             "Number".into(),
             self.string_description(value),
@@ -44,31 +135,107 @@ impl Interpreter {
     }
 
     fn string_description(&self, value: Rc<Value>) -> String {
-        match &*value {
-            Value::String(_, _) => "String".into(),
-            Value::Number(_, _) => "Number".into(),
-            Value::Boolean(_, _) => "Boolean".into(),
-            Value::Nil(_) => "Nil".into(),
-            Value::Callable { .. } => todo!(),
-        }
+        value.string_description()
     }
 
+    /// Create an interpreter that prints to stdout
     pub fn new() -> Self {
-        Self {
-            current_scope: Rc::new(RefCell::new(Environment::new())),
-        }
+        Self::with_output(io::stdout())
+    }
+
+    /// Create an interpreter that writes the output of `print` statements to `output`
+    pub fn with_output(output: impl Write + 'static) -> Self {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        let mut interpreter = Self {
+            globals: globals.clone(),
+            current_scope: globals,
+            source: "".into(),
+            output: Box::new(output),
+            call_stack: vec![],
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            stack_start: 0,
+            budget: Budget::default(),
+        };
+        interpreter.define_native("clock", 0, native::clock);
+        interpreter.define_native("exit", 1, native::exit);
+        interpreter
+    }
+
+    /// Limit how many calls deep Lox code can go before failing with a `Stack overflow` error.
+    /// Each call is evaluated recursively, taking a few kilobytes of native stack in release
+    /// builds and tens of kilobytes in debug builds, so calls also fail once they've used more
+    /// native stack than an ordinary thread can spare. The default depth is only reachable in
+    /// debug builds inside [`with_stack`].
+    pub fn set_max_call_depth(&mut self, max_call_depth: usize) {
+        self.max_call_depth = max_call_depth;
+    }
+
+    /// Limit the resources each call to [`Interpreter::interpret`] can use
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.budget = Budget::new(limits);
+    }
+
+    /// Make a function implemented in Rust available to Lox code as a global. The function is
+    /// only called with exactly `arity` arguments.
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[Rc<Value>], Span) -> Result<Rc<Value>, Error> + 'static,
+    ) {
+        (*self.globals).borrow_mut().define(
+            name.to_string(),
+            Rc::new(Value::NativeFunction(
+                // Given the span of each use when it's looked up
+                Span { start: 0, end: 0 },
+                NativeFunction {
+                    name: name.to_string(),
+                    arity,
+                    function: Rc::new(function),
+                },
+            )),
+        );
+    }
+
+    /// Get the value of a global variable
+    pub fn get_global(&self, name: &str) -> Option<Rc<Value>> {
+        (*self.globals).borrow().get_at(0, name)
+    }
+
+    /// The names of every global variable currently defined, in no particular order
+    pub fn global_names(&self) -> Vec<String> {
+        (*self.globals).borrow().names()
+    }
+
+    /// Lex, parse, resolve and evaluate the given source, keeping any globals it defines for
+    /// later calls
+    pub fn run(&mut self, source: &str) -> Result<(), crate::Error> {
+        let declarations = crate::parse(source)?;
+        self.interpret(source, declarations)
+            .map_err(crate::Error::Runtime)
     }
 
     fn current_scope(&mut self) -> Rc<RefCell<Environment>> {
         self.current_scope.clone()
     }
 
-    fn assign(&mut self, name: String, new_value: Rc<Value>) -> Result<(), ()> {
-        (*self.current_scope).borrow_mut().assign(&name, &new_value)
+    /// Assign to a variable the resolver found `depth` scopes up, or to a global if `depth` is
+    /// `None`
+    fn assign(&mut self, name: &str, depth: Option<usize>, new_value: Rc<Value>) -> Result<(), ()> {
+        match depth {
+            Some(depth) => (*self.current_scope)
+                .borrow_mut()
+                .assign_at(depth, name, &new_value),
+            None => (*self.globals).borrow_mut().assign_at(0, name, &new_value),
+        }
     }
 
-    fn get(&mut self, source: &str, token: Token) -> Option<Rc<Value>> {
-        (*self.current_scope).borrow_mut().get(source, &token)
+    /// Look up a variable the resolver found `depth` scopes up, or a global if `depth` is `None`
+    fn get(&self, name: &str, depth: Option<usize>) -> Option<Rc<Value>> {
+        match depth {
+            Some(depth) => (*self.current_scope).borrow().get_at(depth, name),
+            None => (*self.globals).borrow().get_at(0, name),
+        }
     }
 
     fn define(&mut self, name: String, value: Rc<Value>) {
@@ -86,12 +253,19 @@ impl Interpreter {
         self.current_scope = scope;
     }
 
+    /// Evaluate declarations that have already been parsed and resolved from the given source
     pub fn interpret(&mut self, source: &str, declarations: Vec<Declaration>) -> Result<(), Error> {
+        self.source = source.into();
+        self.budget.start();
+        self.stack_start = stack_address();
         let result = self.evaluate_declarations(source, &declarations);
         match result {
             Ok(_) => Ok(()),
-            Err(ErrorOrReturn::Return(_)) => Ok(()),
-            Err(ErrorOrReturn::Err(error)) => Err(error),
+            Err(Unwind::Return(_)) => Ok(()),
+            Err(Unwind::Err(error)) => Err(error),
+            Err(Unwind::Break | Unwind::Continue) => {
+                unreachable!("The resolver only allows break and continue inside loops")
+            }
         }
     }
 
@@ -99,7 +273,7 @@ impl Interpreter {
         &mut self,
         source: &str,
         declarations: &[Declaration],
-    ) -> Result<(), ErrorOrReturn> {
+    ) -> Result<(), Unwind> {
         declarations
             .iter()
             .try_for_each(|declaration| self.evaluate_declaration(source, declaration))?;
@@ -110,31 +284,76 @@ impl Interpreter {
         &mut self,
         source: &str,
         declaration: &Declaration,
-    ) -> Result<(), ErrorOrReturn> {
+    ) -> Result<(), Unwind> {
         match declaration {
-            Declaration::Function {
+            Declaration::Class {
                 name,
-                parameters,
-                body,
+                superclass,
+                methods,
             } => {
-                let current_scope = self.current_scope();
+                let superclass = if let Some(superclass) = superclass {
+                    if superclass.span().slice(source) == name.span.slice(source) {
+                        return Err(Unwind::Err(Error::ClassInheritsFromItself(
+                            superclass.span(),
+                        )));
+                    };
+                    let value = self
+                        .evaluate_expression(source, superclass.clone())
+                        .map_err(Unwind::Err)?;
+                    let Value::Class(class) = &*value else {
+                        return Err(Unwind::Err(Error::SuperclassMustBeAClass(
+                            superclass.span(),
+                        )));
+                    };
+                    Some(class.clone())
+                } else {
+                    None
+                };
+
+                // Methods of a subclass close over a scope in which `super` refers to the
+                // superclass
+                let class_scope = superclass.as_ref().map(|superclass| {
+                    let old_scope = self.push_scope();
+                    self.define("super".into(), Rc::new(Value::Class(superclass.clone())));
+                    old_scope
+                });
+
+                let methods = methods
+                    .iter()
+                    .map(|method| {
+                        let method_name = method.name.span.slice(source);
+                        (
+                            method_name.to_string(),
+                            self.make_callable(source, method, method_name == "init"),
+                        )
+                    })
+                    .collect::<HashMap<_, _>>();
+
+                if let Some(old_scope) = class_scope {
+                    self.set_scope(old_scope);
+                };
+
                 self.define(
                     name.span.slice(source).to_string(),
-                    Rc::new(Value::Callable(Callable {
-                        environment: current_scope,
+                    Rc::new(Value::Class(Rc::new(Class {
+                        name: name.span.slice(source).to_string(),
                         name_span: name.span,
-                        parameters: parameters
-                            .iter()
-                            .map(|token| token.span.slice(source).to_string())
-                            .collect(),
-                        body: body.clone(),
-                    })),
+                        superclass,
+                        methods,
+                    }))),
+                )
+            }
+            Declaration::Function(function) => {
+                let callable = self.make_callable(source, function, false);
+                self.define(
+                    function.name.span.slice(source).to_string(),
+                    Rc::new(Value::Callable(callable)),
                 )
             }
             Declaration::Variable { name, initialiser } => {
                 let value = if let Some(initialiser) = initialiser {
                     self.evaluate_expression(source, initialiser.clone())
-                        .map_err(ErrorOrReturn::Err)?
+                        .map_err(Unwind::Err)?
                 } else {
                     Rc::new(Value::Nil(name.span))
                 };
@@ -145,28 +364,73 @@ impl Interpreter {
         Ok(())
     }
 
-    fn evaluate_statement(
+    /// Create a callable closing over the current scope from a function declaration
+    fn make_callable(
         &mut self,
         source: &str,
-        statement: &Statement,
-    ) -> Result<(), ErrorOrReturn> {
+        function: &FunctionDeclaration,
+        is_initialiser: bool,
+    ) -> Callable {
+        Callable {
+            environment: self.current_scope(),
+            source: self.source.clone(),
+            name: function.name.span.slice(source).to_string(),
+            name_span: function.name.span,
+            parameters: function
+                .parameters
+                .iter()
+                .map(|token| token.span.slice(source).to_string())
+                .collect(),
+            body: function.body.clone(),
+            is_initialiser,
+        }
+    }
+
+    fn evaluate_block(&mut self, source: &str, declarations: &[Declaration]) -> Result<(), Unwind> {
+        let old_scope = self.push_scope();
+        let result = self.evaluate_declarations(source, declarations);
+        self.set_scope(old_scope);
+        result
+    }
+
+    /// The value a `catch` block receives for an error: whatever was thrown, or an error value
+    /// describing a runtime error
+    fn caught_value(&self, source: &str, error: Error) -> Rc<Value> {
+        match &error.untraced_ref() {
+            Error::Thrown(Thrown { value, .. }) => {
+                let value = value
+                    .clone()
+                    .downcast::<Value>()
+                    .expect("The tree walking interpreter only throws its own values");
+                match &*value {
+                    // An error thrown again has escaped more calls since it was first caught
+                    Value::Error(span, caught) => {
+                        Rc::new(Value::Error(*span, Rc::new(caught.retraced(&error))))
+                    }
+                    _ => value,
+                }
+            }
+            _ => Rc::new(Value::Error(
+                error.span(),
+                Rc::new(ErrorValue::new(&error, source)),
+            )),
+        }
+    }
+
+    fn evaluate_statement(&mut self, source: &str, statement: &Statement) -> Result<(), Unwind> {
         match statement {
             Statement::Print(expression) => {
                 let result = self
                     .evaluate_expression(source, expression.clone())
-                    .map_err(ErrorOrReturn::Err)?;
-                result.pretty_print();
+                    .map_err(Unwind::Err)?;
+                writeln!(self.output, "{result}")
+                    .map_err(|error| Unwind::Err(Error::Output(error, expression.span())))?;
             }
             Statement::Expression(expression) => {
                 self.evaluate_expression(source, expression.clone())
-                    .map_err(ErrorOrReturn::Err)?;
-            }
-            Statement::Block(declarations) => {
-                let old_scope = self.push_scope();
-                let result = self.evaluate_declarations(source, declarations);
-                self.set_scope(old_scope);
-                result?;
+                    .map_err(Unwind::Err)?;
             }
+            Statement::Block(declarations) => self.evaluate_block(source, declarations)?,
             Statement::If {
                 condition,
                 then_branch,
@@ -174,29 +438,147 @@ impl Interpreter {
             } => {
                 let condition = self
                     .evaluate_expression(source, condition.clone())
-                    .map_err(ErrorOrReturn::Err)?;
+                    .map_err(Unwind::Err)?;
                 if self.is_truthy(condition) {
                     self.evaluate_statement(source, then_branch)?;
                 } else if let Some(else_branch) = else_branch {
                     self.evaluate_statement(source, else_branch)?;
                 }
             }
-            Statement::While { condition, body } => {
-                let mut condition_value = self
+            Statement::While {
+                condition,
+                body,
+                increment,
+            } => loop {
+                let condition_value = self
                     .evaluate_expression(source, condition.clone())
-                    .map_err(ErrorOrReturn::Err)?;
-                while self.is_truthy(condition_value) {
-                    self.evaluate_statement(source, body)?;
-                    condition_value = self
-                        .evaluate_expression(source, condition.clone())
-                        .map_err(ErrorOrReturn::Err)?
+                    .map_err(Unwind::Err)?;
+                if !self.is_truthy(condition_value) {
+                    break;
+                }
+
+                match self.evaluate_statement(source, body) {
+                    Ok(()) | Err(Unwind::Continue) => {}
+                    Err(Unwind::Break) => break,
+                    Err(unwind) => return Err(unwind),
+                }
+
+                if let Some(increment) = increment {
+                    self.evaluate_expression(source, increment.clone())
+                        .map_err(Unwind::Err)?;
+                }
+            },
+            Statement::ForIn {
+                variable,
+                iterable,
+                body,
+            } => {
+                let iterable = self
+                    .evaluate_expression(source, iterable.clone())
+                    .map_err(Unwind::Err)?;
+                // Maps are iterated over a snapshot of their keys. Lists are read live, so
+                // elements pushed during the loop are visited too
+                let keys = match &*iterable {
+                    Value::List(_, _) => None,
+                    Value::Map(span, map) => Some(
+                        map.borrow()
+                            .keys()
+                            .map(|key| Rc::new(Value::from_map_key(key, *span)))
+                            .collect::<Vec<_>>(),
+                    ),
+                    _ => {
+                        return Err(Unwind::Err(Error::NotIterable(iterable.span())));
+                    }
+                };
+
+                let mut index = 0;
+                loop {
+                    let element = match (&keys, &*iterable) {
+                        (Some(keys), _) => keys.get(index).cloned(),
+                        (None, Value::List(_, list)) => list.borrow().get(index).cloned(),
+                        _ => unreachable!("Only lists and maps are iterable"),
+                    };
+                    let Some(element) = element else {
+                        break;
+                    };
+                    index += 1;
+
+                    let old_scope = self.push_scope();
+                    self.define(variable.span.slice(source).to_string(), element);
+                    let result = self.evaluate_statement(source, body);
+                    self.set_scope(old_scope);
+                    match result {
+                        Ok(()) | Err(Unwind::Continue) => {}
+                        Err(Unwind::Break) => break,
+                        Err(unwind) => return Err(unwind),
+                    }
                 }
             }
-            Statement::Return { value, .. } => {
-                let result = self
+            Statement::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                let mut result = self.evaluate_block(source, body);
+                if let Some(CatchClause { name, body }) = catch {
+                    result = match result {
+                        Err(Unwind::Err(error)) if error.is_catchable() => {
+                            let caught = self.caught_value(source, error);
+                            let old_scope = self.push_scope();
+                            self.define(name.span.slice(source).to_string(), caught);
+                            let result = self.evaluate_declarations(source, body);
+                            self.set_scope(old_scope);
+                            result
+                        }
+                        result => result,
+                    };
+                }
+                // Anything the finally block does, like returning or throwing, replaces what
+                // the try and catch blocks did. Errors that can't be caught skip it, so it can't
+                // keep the script going.
+                let is_uncatchable =
+                    matches!(&result, Err(Unwind::Err(error)) if !error.is_catchable());
+                if let Some(finally) = finally
+                    && !is_uncatchable
+                {
+                    self.evaluate_block(source, finally)?;
+                }
+                result?;
+            }
+            Statement::Throw { keyword, value } => {
+                let value = self
                     .evaluate_expression(source, value.clone())
-                    .map_err(ErrorOrReturn::Err)?;
-                return Err(ErrorOrReturn::Return(result));
+                    .map_err(Unwind::Err)?;
+                // Rethrown errors are reported where they originally happened, inside the calls
+                // they had escaped before they were caught
+                let (message, span, trace) = match &*value {
+                    Value::Error(_, error) => {
+                        (error.message.clone(), error.span, error.trace.clone())
+                    }
+                    value => (
+                        format!("Uncaught exception: {value}"),
+                        keyword.span.combine(value.span()),
+                        vec![],
+                    ),
+                };
+                let error = Error::Thrown(Thrown {
+                    value,
+                    message,
+                    span,
+                });
+                return Err(Unwind::Err(error.escaped(trace)));
+            }
+            Statement::Break(_) => return Err(Unwind::Break),
+            Statement::Continue(_) => return Err(Unwind::Continue),
+            Statement::Return { keyword, value } => {
+                let result = match value {
+                    Some(value) => self
+                        .evaluate_expression(source, value.clone())
+                        .map_err(Unwind::Err)?,
+                    None => Rc::new(Value::Nil(keyword.span)),
+                };
+                return Err(Unwind::Return(result));
             }
         };
         Ok(())
@@ -207,14 +589,15 @@ impl Interpreter {
         source: &str,
         expression: Rc<Expression>,
     ) -> Result<Rc<Value>, Error> {
+        self.budget.step(|| expression.span())?;
         match &*expression {
-            Expression::Assignment(AssignmentExpression { name, value }) => {
+            Expression::Assignment(AssignmentExpression { name, value, depth }) => {
                 let value = self.evaluate_expression(source, value.clone())?;
-                let did_assign = self.assign(name.span.slice(source).to_string(), value.clone());
+                let did_assign = self.assign(name.span.slice(source), depth.get(), value.clone());
                 if did_assign.is_ok() {
                     return Ok(value);
                 };
-                Err(Error::VariableDoesntExist((*name).clone()))
+                Err(self.undefined_variable(source, name))
             }
             Expression::Binary(BinaryExpression {
                 left,
@@ -226,11 +609,118 @@ impl Interpreter {
                 arguments,
                 closing_paren,
             }) => self.evaluate_call(source, callee.clone(), closing_paren.clone(), arguments),
-            Expression::Get(_) => todo!(),
+            Expression::Get(GetExpression { object, name }) => {
+                let object = self.evaluate_expression(source, object.clone())?;
+                let native_method = match &*object {
+                    Value::List(_, list) => Some(native::list_method(
+                        list,
+                        name.span.slice(source),
+                        self.budget.max_collection_size(),
+                    )),
+                    Value::Map(_, map) => Some(native::map_method(map, name.span.slice(source))),
+                    Value::Error(_, error) => {
+                        return native::error_property(error, name.span.slice(source), name.span)
+                            .map(Rc::new)
+                            .ok_or_else(|| Error::UndefinedProperty(name.clone()));
+                    }
+                    _ => None,
+                };
+                if let Some(native_method) = native_method {
+                    return native_method
+                        .map(|method| Rc::new(Value::NativeFunction(expression.span(), method)))
+                        .ok_or_else(|| Error::UndefinedProperty(name.clone()));
+                };
+                let Value::Instance(_, instance) = &*object else {
+                    return Err(Error::OnlyInstancesHaveProperties(expression.span()));
+                };
+                let property_name = name.span.slice(source);
+
+                if let Some(field) = instance.borrow().fields.get(property_name) {
+                    return Ok(field.clone());
+                };
+
+                let class = instance.borrow().class.clone();
+                if let Some(method) = class.find_method(property_name) {
+                    return Ok(Rc::new(Value::Callable(method.bind(object.clone()))));
+                };
+
+                Err(Error::UndefinedProperty(name.clone()))
+            }
+            Expression::Function(FunctionExpression {
+                keyword,
+                parameters,
+                body,
+            }) => Ok(Rc::new(Value::Callable(Callable {
+                environment: self.current_scope(),
+                source: self.source.clone(),
+                name: String::new(),
+                name_span: keyword.span,
+                parameters: parameters
+                    .iter()
+                    .map(|token| token.span.slice(source).to_string())
+                    .collect(),
+                body: body.clone(),
+                is_initialiser: false,
+            }))),
             Expression::Grouping(GroupingExpression { expression }) => {
                 self.evaluate_expression(source, expression.clone())
             }
+            Expression::Index(IndexExpression { object, index, .. }) => {
+                let collection = self.evaluate_expression(source, object.clone())?;
+                let index_value = self.evaluate_expression(source, index.clone())?;
+                match &*collection {
+                    Value::List(_, list) => {
+                        let list = list.borrow();
+                        let index = native::list_index(&index_value, list.len(), index.span())?;
+                        Ok(list[index].clone())
+                    }
+                    // Missing keys read as nil
+                    Value::Map(_, map) => {
+                        let key = native::map_key(&index_value, index.span())?;
+                        Ok(map
+                            .borrow()
+                            .get(&key)
+                            .cloned()
+                            .unwrap_or_else(|| Rc::new(Value::Nil(expression.span()))))
+                    }
+                    _ => Err(Error::NotIndexable(object.span())),
+                }
+            }
+            Expression::Interpolation(InterpolationExpression { parts }) => {
+                let mut string = String::new();
+                for part in parts {
+                    let value = self.evaluate_expression(source, part.clone())?;
+                    string.push_str(&value.to_string());
+                }
+                self.budget.check_size(string.len(), expression.span())?;
+                Ok(Rc::new(Value::String(expression.span(), string)))
+            }
+            Expression::List(ListExpression { elements, .. }) => {
+                let elements = elements
+                    .iter()
+                    .map(|element| self.evaluate_expression(source, element.clone()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.budget.check_size(elements.len(), expression.span())?;
+                Ok(Rc::new(Value::List(
+                    expression.span(),
+                    Rc::new(RefCell::new(elements)),
+                )))
+            }
             Expression::Literal(literal) => self.evaluate_literal(source, literal),
+            Expression::Map(MapExpression { entries, .. }) => {
+                let mut map = Map::default();
+                for (key, value) in entries {
+                    let key_value = self.evaluate_expression(source, key.clone())?;
+                    let key = native::map_key(&key_value, key.span())?;
+                    let value = self.evaluate_expression(source, value.clone())?;
+                    map.insert(key, value);
+                }
+                self.budget.check_size(map.len(), expression.span())?;
+                Ok(Rc::new(Value::Map(
+                    expression.span(),
+                    Rc::new(RefCell::new(map)),
+                )))
+            }
             Expression::Logical(LogicalExpression {
                 left,
                 right,
@@ -245,25 +735,107 @@ impl Interpreter {
                 }
                 self.evaluate_expression(source, right.clone())
             }
-            Expression::Set(_) => todo!(),
-            Expression::Super(_) => todo!(),
-            Expression::This(_) => todo!(),
+            Expression::Set(SetExpression {
+                object,
+                name,
+                value,
+            }) => {
+                let object = self.evaluate_expression(source, object.clone())?;
+                let Value::Instance(_, instance) = &*object else {
+                    return Err(Error::OnlyInstancesHaveProperties(expression.span()));
+                };
+                let value = self.evaluate_expression(source, value.clone())?;
+                instance
+                    .borrow_mut()
+                    .fields
+                    .insert(name.span.slice(source).to_string(), value.clone());
+                Ok(value)
+            }
+            Expression::SetIndex(SetIndexExpression {
+                object,
+                index,
+                value,
+                ..
+            }) => {
+                let collection = self.evaluate_expression(source, object.clone())?;
+                let index_value = self.evaluate_expression(source, index.clone())?;
+                let value = self.evaluate_expression(source, value.clone())?;
+                match &*collection {
+                    Value::List(_, list) => {
+                        let mut list = list.borrow_mut();
+                        let index = native::list_index(&index_value, list.len(), index.span())?;
+                        list[index] = value.clone();
+                    }
+                    Value::Map(_, map) => {
+                        let key = native::map_key(&index_value, index.span())?;
+                        let mut map = map.borrow_mut();
+                        if !map.contains_key(&key) {
+                            self.budget.check_size(map.len() + 1, expression.span())?;
+                        }
+                        map.insert(key, value.clone());
+                    }
+                    _ => return Err(Error::NotIndexable(object.span())),
+                }
+                Ok(value)
+            }
+            Expression::Super(SuperExpression {
+                keyword,
+                method,
+                depth,
+            }) => {
+                let superclass =
+                    self.get("super", depth.get())
+                        .ok_or_else(|| Error::VariableDoesntExist {
+                            name: keyword.clone(),
+                            suggestion: None,
+                        })?;
+                let Value::Class(superclass) = &*superclass else {
+                    return Err(Error::SuperclassMustBeAClass(keyword.span));
+                };
+                // `this` is always bound in the scope just inside the one `super` is bound in
+                let instance = self
+                    .get("this", depth.get().map(|depth| depth - 1))
+                    .ok_or_else(|| Error::VariableDoesntExist {
+                        name: keyword.clone(),
+                        suggestion: None,
+                    })?;
+
+                superclass
+                    .find_method(method.span.slice(source))
+                    .map(|method| Rc::new(Value::Callable(method.bind(instance))))
+                    .ok_or_else(|| Error::UndefinedProperty(method.clone()))
+            }
+            Expression::This(ThisExpression { keyword, depth }) => self
+                .get("this", depth.get())
+                .ok_or_else(|| Error::VariableDoesntExist {
+                    name: keyword.clone(),
+                    suggestion: None,
+                }),
             Expression::Unary(UnaryExpression { operator, right }) => {
                 self.evaluate_unary_expression(source, operator.clone(), right.clone())
             }
-            Expression::Variable(VariableExpression { name }) => {
-                let token = name;
-                self.get(source, token.clone())
-                    .ok_or_else(|| Error::VariableDoesntExist(token.clone()))
+            Expression::Variable(VariableExpression { name, depth }) => {
+                let value = self
+                    .get(name.span.slice(source), depth.get())
+                    .ok_or_else(|| self.undefined_variable(source, name))?;
+                match &*value {
+                    Value::NativeFunction(_, native_function) => Ok(Rc::new(
+                        Value::NativeFunction(name.span, native_function.clone()),
+                    )),
+                    _ => Ok(value),
+                }
             }
         }
     }
 
-    fn value_as_callable(&self, value: Rc<Value>, name_span: Span) -> Result<Callable, Error> {
-        if let Value::Callable(callable) = &*value {
-            Ok(callable.clone())
-        } else {
-            Err(Error::NotCallable(name_span))
+    /// The error for a variable that doesn't exist, suggesting one in scope with a similar name
+    fn undefined_variable(&self, source: &str, name: &Token) -> Error {
+        // The current scope is always nested in the globals, so this includes them too
+        let names = (*self.current_scope).borrow().visible_names();
+        let suggestion = closest_name(name.span.slice(source), names.iter().map(String::as_str));
+        Error::VariableDoesntExist {
+            name: name.clone(),
+            suggestion: suggestion.map(String::from),
         }
     }
 
@@ -275,14 +847,26 @@ impl Interpreter {
         arguments: &[Rc<Expression>],
     ) -> Result<Rc<Value>, Error> {
         let callee_span = callee.span();
+        let call_span = callee_span.combine(closing_paren.span);
         let callee = self.evaluate_expression(source, callee)?;
-        let callee = self.value_as_callable(callee, callee_span)?;
 
-        if callee.parameters.len() != arguments.len() {
+        let (expected_arguments, declaration) = match &*callee {
+            Value::Callable(callable) => (callable.parameters.len(), Some(callable)),
+            Value::NativeFunction(_, native_function) => (native_function.arity, None),
+            Value::Class(class) => (class.arity(), class.find_method("init")),
+            _ => return Err(Error::NotCallable(callee_span)),
+        };
+
+        if expected_arguments != arguments.len() {
+            // Functions declared by earlier runs point into source that isn't being shown
+            let declaration_span = declaration
+                .filter(|callable| Rc::ptr_eq(&callable.source, &self.source))
+                .map(|callable| callable.name_span);
             return Err(Error::Arity {
-                expected: callee.parameters.len(),
+                expected: expected_arguments,
                 got: arguments.len(),
-                call_span: callee_span.combine(closing_paren.span),
+                call_span,
+                declaration_span,
             });
         };
 
@@ -293,20 +877,81 @@ impl Interpreter {
             argument_values.push(argument_value);
         }
 
+        match &*callee {
+            Value::Callable(callable) => self.call(callable, argument_values, call_span),
+            Value::NativeFunction(_, native_function) => {
+                (native_function.function)(&argument_values, call_span)
+            }
+            Value::Class(class) => {
+                let instance = Rc::new(Value::Instance(
+                    call_span,
+                    Rc::new(RefCell::new(Instance::new(class.clone()))),
+                ));
+                if let Some(initialiser) = class.find_method("init") {
+                    self.call(
+                        &initialiser.bind(instance.clone()),
+                        argument_values,
+                        call_span,
+                    )?;
+                };
+                Ok(instance)
+            }
+            _ => unreachable!("Callee has already been checked to be callable"),
+        }
+    }
+
+    /// Call a callable with already evaluated arguments. The caller is responsible for checking
+    /// the number of arguments matches the callable's parameters
+    fn call(
+        &mut self,
+        callable: &Callable,
+        arguments: Vec<Rc<Value>>,
+        call_span: Span,
+    ) -> Result<Rc<Value>, Error> {
+        let stack_used = self.stack_start.abs_diff(stack_address());
+        if self.call_stack.len() >= self.max_call_depth || stack_used > NATIVE_STACK_BUDGET.get() {
+            return Err(Error::StackOverflow(call_span));
+        }
+        self.call_stack.push(TraceFrame {
+            function: callable.name.clone(),
+            call_span,
+            source: callable.source.clone(),
+        });
         let old_scope = self.current_scope.clone();
         self.set_scope(Rc::new(RefCell::new(Environment::close_over(
-            callee.environment,
+            callable.environment.clone(),
         ))));
-        for (paramater_name, argument) in callee.parameters.iter().zip(argument_values.iter()) {
-            self.define(paramater_name.to_owned(), argument.clone())
+        for (paramater_name, argument) in callable.parameters.iter().zip(arguments) {
+            self.define(paramater_name.to_owned(), argument)
         }
-        let result = self.evaluate_declarations(source, &callee.body);
+        // The body has to be evaluated against the source it was declared in
+        let old_source = std::mem::replace(&mut self.source, callable.source.clone());
+        let result = self.evaluate_declarations(&callable.source, &callable.body);
+        self.source = old_source;
         self.set_scope(old_scope);
 
+        let frame = self.call_stack.pop();
+        let result = match result {
+            Err(Unwind::Err(error)) => Err(Unwind::Err(error.escaped(frame))),
+            result => result,
+        };
+
+        // Initialisers always return the instance they were called on
+        if callable.is_initialiser && !matches!(result, Err(Unwind::Err(_))) {
+            return Ok(callable
+                .environment
+                .borrow()
+                .get_at(0, "this")
+                .expect("Initialisers should always be bound to an instance"));
+        };
+
         match result {
             Ok(_) => Ok(Rc::new(Value::Nil(Span::new(0, 0)))),
-            Err(ErrorOrReturn::Return(value)) => Ok(value),
-            Err(ErrorOrReturn::Err(error)) => Err(error),
+            Err(Unwind::Return(value)) => Ok(value),
+            Err(Unwind::Err(error)) => Err(error),
+            Err(Unwind::Break | Unwind::Continue) => {
+                unreachable!("The resolver only allows break and continue inside loops")
+            }
         }
     }
 
@@ -317,20 +962,24 @@ impl Interpreter {
         right: Rc<Expression>,
     ) -> Result<Rc<Value>, Error> {
         use TokenType::*;
+        let span = operator.span.combine(right.span());
         let right = self.evaluate_expression(source, right)?;
         Ok(Rc::new(match operator.type_ {
             LeftParen => todo!(),
             RightParen => todo!(),
             LeftBrace => todo!(),
             RightBrace => todo!(),
+            LeftBracket => todo!(),
+            RightBracket => todo!(),
+            Colon => todo!(),
             Comma => todo!(),
             Dot => todo!(),
-            Minus => Value::Number(operator.span.combine(right.span()), -self.as_number(right)?),
+            Minus => Value::Number(span, -self.as_number(right, span)?),
             Plus => todo!(),
             Semicolon => todo!(),
             Slash => todo!(),
             Star => todo!(),
-            Bang => Value::Boolean(operator.span.combine(right.span()), !self.is_truthy(right)),
+            Bang => Value::Boolean(span, !self.is_truthy(right)),
             BangEqual => todo!(),
             Equal => todo!(),
             EqualEqual => todo!(),
@@ -338,16 +987,21 @@ impl Interpreter {
             GreaterEqual => todo!(),
             Less => todo!(),
             LessEqual => todo!(),
+            Arrow => todo!(),
             Identifier => todo!(),
             String_ => todo!(),
+            Interpolation => todo!(),
             Number => todo!(),
             And => todo!(),
+            Break => todo!(),
             Class => todo!(),
+            Continue => todo!(),
             Else => todo!(),
             False => todo!(),
             Fun => todo!(),
             For => todo!(),
             If => todo!(),
+            In => todo!(),
             Nil => todo!(),
             Or => todo!(),
             Print => todo!(),
@@ -357,6 +1011,10 @@ impl Interpreter {
             True => todo!(),
             Var => todo!(),
             While => todo!(),
+            Try => todo!(),
+            Catch => todo!(),
+            Finally => todo!(),
+            Throw => todo!(),
             Eof => todo!(),
         }))
     }
@@ -367,12 +1025,7 @@ impl Interpreter {
         literal: &LiteralExpression,
     ) -> Result<Rc<Value>, Error> {
         Ok(Rc::new(match literal {
-            LiteralExpression::String_(value) => Value::String(
-                value.span,
-                Span::new(value.span.start + 1, value.span.end - 1)
-                    .slice(source)
-                    .to_owned(),
-            ),
+            LiteralExpression::String_(token, value) => Value::String(token.span, value.clone()),
             LiteralExpression::Number(value) => Value::Number(
                 value.span,
                 value.span.slice(source).parse().unwrap_or_else(|_| {
@@ -391,6 +1044,12 @@ impl Interpreter {
             Value::Boolean(_, value) => *value,
             Value::Nil(_) => false,
             Value::Callable { .. } => true,
+            Value::NativeFunction(..) => true,
+            Value::Class(_) => true,
+            Value::Instance(_, _) => true,
+            Value::List(_, _) => true,
+            Value::Map(_, _) => true,
+            Value::Error(_, _) => true,
         }
     }
 
@@ -408,89 +1067,93 @@ impl Interpreter {
             RightParen => todo!(),
             LeftBrace => todo!(),
             RightBrace => todo!(),
+            LeftBracket => todo!(),
+            RightBracket => todo!(),
+            Colon => todo!(),
             Comma => todo!(),
             Dot => todo!(),
             Minus => {
                 let left = self.evaluate_expression(source, left)?;
-                let left = self.as_number(left)?;
+                let left = self.as_number(left, span)?;
                 let right = self.evaluate_expression(source, right)?;
-                let right = self.as_number(right)?;
+                let right = self.as_number(right, span)?;
                 Rc::new(Value::Number(span, left - right))
             }
             Plus => {
                 let left = self.evaluate_expression(source, left)?;
                 let right = self.evaluate_expression(source, right)?;
-                self.plus_or_concat(left, right)?
+                self.plus_or_concat(left, right, span)?
             }
             Semicolon => todo!(),
             Slash => {
                 let left = self.evaluate_expression(source, left)?;
-                let left = self.as_number(left)?;
+                let left = self.as_number(left, span)?;
                 let right = self.evaluate_expression(source, right)?;
-                let right = self.as_number(right)?;
+                let right = self.as_number(right, span)?;
                 Rc::new(Value::Number(span, left / right))
             }
             Star => {
                 let left = self.evaluate_expression(source, left)?;
-                let left = self.as_number(left)?;
+                let left = self.as_number(left, span)?;
                 let right = self.evaluate_expression(source, right)?;
-                let right = self.as_number(right)?;
+                let right = self.as_number(right, span)?;
                 Rc::new(Value::Number(span, left * right))
             }
             Bang => todo!(),
             BangEqual => {
                 let left = self.evaluate_expression(source, left)?;
-                let left = self.as_number(left)?;
                 let right = self.evaluate_expression(source, right)?;
-                let right = self.as_number(right)?;
-                Rc::new(Value::Boolean(span, left != right))
+                Rc::new(Value::Boolean(span, !left.equals(&right)))
             }
             Equal => todo!(),
             EqualEqual => {
                 let left = self.evaluate_expression(source, left)?;
-                let left = self.as_number(left)?;
                 let right = self.evaluate_expression(source, right)?;
-                let right = self.as_number(right)?;
-                Rc::new(Value::Boolean(span, left == right))
+                Rc::new(Value::Boolean(span, left.equals(&right)))
             }
             Greater => {
                 let left = self.evaluate_expression(source, left)?;
-                let left = self.as_number(left)?;
+                let left = self.as_number(left, span)?;
                 let right = self.evaluate_expression(source, right)?;
-                let right = self.as_number(right)?;
+                let right = self.as_number(right, span)?;
                 Rc::new(Value::Boolean(span, left > right))
             }
             GreaterEqual => {
                 let left = self.evaluate_expression(source, left)?;
-                let left = self.as_number(left)?;
+                let left = self.as_number(left, span)?;
                 let right = self.evaluate_expression(source, right)?;
-                let right = self.as_number(right)?;
+                let right = self.as_number(right, span)?;
                 Rc::new(Value::Boolean(span, left >= right))
             }
             Less => {
                 let left = self.evaluate_expression(source, left)?;
-                let left = self.as_number(left)?;
+                let left = self.as_number(left, span)?;
                 let right = self.evaluate_expression(source, right)?;
-                let right = self.as_number(right)?;
+                let right = self.as_number(right, span)?;
                 Rc::new(Value::Boolean(span, left < right))
             }
             LessEqual => {
                 let left = self.evaluate_expression(source, left)?;
-                let left = self.as_number(left)?;
+                let left = self.as_number(left, span)?;
                 let right = self.evaluate_expression(source, right)?;
-                let right = self.as_number(right)?;
+                let right = self.as_number(right, span)?;
                 Rc::new(Value::Boolean(span, left <= right))
             }
+            Arrow => todo!(),
             Identifier => todo!(),
             String_ => todo!(),
+            Interpolation => todo!(),
             Number => todo!(),
             And => todo!(),
+            Break => todo!(),
             Class => todo!(),
+            Continue => todo!(),
             Else => todo!(),
             False => todo!(),
             Fun => todo!(),
             For => todo!(),
             If => todo!(),
+            In => todo!(),
             Nil => todo!(),
             Or => todo!(),
             Print => todo!(),
@@ -500,37 +1163,68 @@ impl Interpreter {
             True => todo!(),
             Var => todo!(),
             While => todo!(),
+            Try => todo!(),
+            Catch => todo!(),
+            Finally => todo!(),
+            Throw => todo!(),
             Eof => todo!(),
         })
     }
 
-    fn as_string(&self, value: Rc<Value>) -> Result<String, Error> {
+    /// The string a value holds, or a type error pointing at `span`, the expression that needed
+    /// a string
+    fn as_string(&self, value: Rc<Value>, span: Span) -> Result<String, Error> {
         match &*value {
             Value::String(_, string) => Ok(string.to_owned()),
-            Value::Number(span, _) => Err(Error::type_error(
+            Value::Number(..) => Err(Error::type_error(
                 "String".to_string(),
                 "Number".to_string(),
-                *span,
+                span,
+            )),
+            Value::Boolean(_, _)
+            | Value::Nil(_)
+            | Value::Callable { .. }
+            | Value::NativeFunction(..)
+            | Value::Class(_)
+            | Value::Instance(_, _)
+            | Value::List(_, _)
+            | Value::Map(_, _)
+            | Value::Error(_, _) => Err(Error::type_error(
+                "String".to_string(),
+                self.string_description(value.clone()),
+                span,
             )),
-            Value::Boolean(_, _) => todo!(),
-            Value::Nil(_) => todo!(),
-            Value::Callable { .. } => todo!(),
         }
     }
 
-    fn plus_or_concat(&self, left: Rc<Value>, right: Rc<Value>) -> Result<Rc<Value>, Error> {
+    fn plus_or_concat(
+        &self,
+        left: Rc<Value>,
+        right: Rc<Value>,
+        span: Span,
+    ) -> Result<Rc<Value>, Error> {
         Ok(Rc::new(match &*left {
-            Value::String(left_span, left) => Value::String(
-                left_span.combine(right.span()),
-                left.to_owned() + &self.as_string(right)?,
-            ),
-            Value::Number(left_span, left) => Value::Number(
-                left_span.combine(right.span()),
-                left + self.as_number(right)?,
-            ),
-            Value::Boolean(_, _) => todo!(),
-            Value::Nil(_) => todo!(),
-            Value::Callable { .. } => todo!(),
+            Value::String(_, left) => {
+                let string = left.to_owned() + &self.as_string(right, span)?;
+                self.budget.check_size(string.len(), span)?;
+                Value::String(span, string)
+            }
+            Value::Number(_, left) => Value::Number(span, left + self.as_number(right, span)?),
+            Value::Boolean(_, _)
+            | Value::Nil(_)
+            | Value::Callable { .. }
+            | Value::NativeFunction(..)
+            | Value::Class(_)
+            | Value::Instance(_, _)
+            | Value::List(_, _)
+            | Value::Map(_, _)
+            | Value::Error(_, _) => {
+                return Err(Error::type_error(
+                    "Number".to_string(),
+                    self.string_description(left.clone()),
+                    span,
+                ))
+            }
         }))
     }
 }
@@ -1,7 +1,5 @@
 use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
-use crate::lexer::Token;
-
 use super::value::Value;
 
 #[derive(Debug)]
@@ -29,17 +27,38 @@ impl Environment {
         self.values.insert(name, value);
     }
 
-    pub(crate) fn get(&self, source: &str, token: &Token) -> Option<Rc<Value>> {
-        let value = self.values.get(token.span.slice(source)).cloned();
-        if value.is_some() {
-            return value;
+    pub(crate) fn names(&self) -> Vec<String> {
+        self.values.keys().cloned().collect()
+    }
+
+    /// The names of every variable in this environment and the ones it's nested in
+    pub(crate) fn visible_names(&self) -> Vec<String> {
+        let mut names = self.names();
+        if let Some(parent) = &self.parent {
+            names.extend((**parent).borrow().visible_names());
+        };
+        names
+    }
+
+    /// Get the value of a variable declared `distance` environments up the parent chain
+    pub(crate) fn get_at(&self, distance: usize, name: &str) -> Option<Rc<Value>> {
+        if distance == 0 {
+            return self.values.get(name).cloned();
         };
-        (*(self.parent.as_ref()?)).borrow().get(source, token)
+        (*(self.parent.as_ref()?))
+            .borrow()
+            .get_at(distance - 1, name)
     }
 
-    pub(crate) fn assign(&mut self, name: &String, new_value: &Rc<Value>) -> Result<(), ()> {
-        let value = self.values.get_mut(name);
-        if let Some(value) = value {
+    /// Assign to a variable declared `distance` environments up the parent chain
+    pub(crate) fn assign_at(
+        &mut self,
+        distance: usize,
+        name: &str,
+        new_value: &Rc<Value>,
+    ) -> Result<(), ()> {
+        if distance == 0 {
+            let value = self.values.get_mut(name).ok_or(())?;
             *value = new_value.clone();
             return Ok(());
         };
@@ -49,6 +68,6 @@ impl Environment {
 
         (*self.parent.clone().unwrap())
             .borrow_mut()
-            .assign(name, new_value)
+            .assign_at(distance - 1, name, new_value)
     }
 }
@@ -1,20 +1,164 @@
+use std::{
+    any::Any,
+    fmt,
+    io::{self, Write},
+    rc::Rc,
+    time::Duration,
+};
+
 use crate::{
-    lexer::{self, Token},
+    diagnostic::{Color, Diagnostic},
+    lexer::Token,
+    source_map::SourceMap,
     span::Span,
 };
 
 #[derive(Debug)]
 pub enum Error {
     Type(TypeError),
-    VariableDoesntExist(Token),
+    VariableDoesntExist {
+        name: Token,
+        /// A variable in scope with a similar name, which might have been meant instead
+        suggestion: Option<String>,
+    },
     NotCallable(Span),
+    OnlyInstancesHaveProperties(Span),
+    UndefinedProperty(Token),
+    SuperclassMustBeAClass(Span),
+    ClassInheritsFromItself(Span),
     Arity {
         got: usize,
         expected: usize,
         call_span: Span,
+        /// Where the function called was declared, if it was declared in Lox
+        declaration_span: Option<Span>,
+    },
+    /// Writing the result of a `print` statement to the interpreter's output failed
+    Output(io::Error, Span),
+    NotIndexable(Span),
+    IndexOutOfRange {
+        index: f64,
+        length: usize,
+        span: Span,
+    },
+    IndexNotWholeNumber(Span),
+    PopFromEmptyList(Span),
+    UnhashableKey {
+        type_name: String,
+        span: Span,
+    },
+    NotIterable(Span),
+    /// A call was made while already [`max_call_depth`](crate::Interpreter::set_max_call_depth)
+    /// calls deep
+    StackOverflow(Span),
+    /// The script took more steps than [`Limits::max_steps`](crate::limits::Limits) allows
+    StepLimitExceeded {
+        limit: u64,
+        span: Span,
+    },
+    /// The script ran for longer than [`Limits::timeout`](crate::limits::Limits) allows
+    TimedOut {
+        timeout: Duration,
+        span: Span,
+    },
+    /// A list, map or string grew bigger than
+    /// [`Limits::max_collection_size`](crate::limits::Limits) allows
+    CollectionTooLarge {
+        limit: usize,
+        span: Span,
+    },
+    /// The script's [`CancellationHandle`](crate::limits::CancellationHandle) was cancelled
+    Cancelled(Span),
+    /// The script called `exit`, which stops it without running any more `catch` or `finally`
+    /// blocks
+    Exit {
+        code: u8,
+        span: Span,
+    },
+    InvalidExitCode {
+        code: f64,
+        span: Span,
+    },
+    /// A value thrown by a `throw` statement. Each backend stores its own kind of value, which
+    /// `catch` blocks get back by downcasting.
+    Thrown(Thrown),
+    /// An error that escaped from inside function calls, with the calls it has escaped so far,
+    /// innermost first
+    Traced {
+        error: Box<Error>,
+        trace: Vec<TraceFrame>,
     },
 }
 
+/// A function call that was being evaluated when a runtime error happened
+#[derive(Debug, Clone)]
+pub struct TraceFrame {
+    /// The name of the function called, or an empty string for anonymous functions
+    pub function: String,
+    pub call_span: Span,
+    /// The source the called function was declared in, which errors raised inside it point
+    /// into. It's from an earlier run if the function was declared by one.
+    pub source: Rc<str>,
+}
+
+pub struct Thrown {
+    pub value: Rc<dyn Any>,
+    /// What to report if the value is never caught
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Debug for Thrown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Thrown")
+            .field("message", &self.message)
+            .field("span", &self.span)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A runtime error caught by a `catch` block, as seen by Lox code. Its `message` and `line` can
+/// be read as properties.
+#[derive(Debug)]
+pub struct ErrorValue {
+    pub message: String,
+    pub line: usize,
+    /// Where the error happened, so it's reported in the same place if it's thrown again
+    pub span: Span,
+    /// The calls the error escaped before it was caught, innermost first, so they're still
+    /// reported if it's thrown again
+    pub trace: Vec<TraceFrame>,
+}
+
+impl ErrorValue {
+    pub(crate) fn new(error: &Error, source: &str) -> Self {
+        let source = error.raised_in(source);
+        let span = error.span();
+        Self {
+            message: error.message(source),
+            line: span.line(source),
+            span,
+            trace: error.trace().to_vec(),
+        }
+    }
+
+    /// The same error, having escaped the calls `error` has
+    pub(crate) fn retraced(&self, error: &Error) -> Self {
+        Self {
+            message: self.message.clone(),
+            line: self.line,
+            span: self.span,
+            trace: error.trace().to_vec(),
+        }
+    }
+}
+
+impl fmt::Display for ErrorValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
 #[derive(Debug)]
 pub struct TypeError {
     expected: String,
@@ -33,35 +177,269 @@ impl Error {
 }
 
 impl Error {
-    pub fn display(&self, source: &str) {
+    pub fn span(&self) -> Span {
         match self {
             Error::Type(TypeError {
-                expected,
-                got,
-                source_token_span,
-            }) => lexer::Error::display_error(
-                source,
-                source_token_span,
-                &format!("Type Error: expected {}, got {}", expected, got),
+                source_token_span, ..
+            }) => *source_token_span,
+            Error::VariableDoesntExist { name: token, .. } | Error::UndefinedProperty(token) => {
+                token.span
+            }
+            Error::NotCallable(span)
+            | Error::OnlyInstancesHaveProperties(span)
+            | Error::SuperclassMustBeAClass(span)
+            | Error::ClassInheritsFromItself(span)
+            | Error::Arity {
+                call_span: span, ..
+            }
+            | Error::Output(_, span)
+            | Error::NotIndexable(span)
+            | Error::IndexOutOfRange { span, .. }
+            | Error::IndexNotWholeNumber(span)
+            | Error::PopFromEmptyList(span)
+            | Error::UnhashableKey { span, .. }
+            | Error::NotIterable(span)
+            | Error::StackOverflow(span)
+            | Error::StepLimitExceeded { span, .. }
+            | Error::TimedOut { span, .. }
+            | Error::CollectionTooLarge { span, .. }
+            | Error::Cancelled(span)
+            | Error::Exit { span, .. }
+            | Error::InvalidExitCode { span, .. }
+            | Error::Thrown(Thrown { span, .. }) => *span,
+            Error::Traced { error, .. } => error.span(),
+        }
+    }
+
+    /// The error without the stack trace it was given while escaping function calls
+    pub fn untraced(self) -> Error {
+        match self {
+            Error::Traced { error, .. } => *error,
+            error => error,
+        }
+    }
+
+    /// The error without its stack trace, without taking ownership of it
+    pub fn untraced_ref(&self) -> &Error {
+        match self {
+            Error::Traced { error, .. } => error,
+            error => error,
+        }
+    }
+
+    /// The calls the error has escaped so far, innermost first
+    pub fn trace(&self) -> &[TraceFrame] {
+        match self {
+            Error::Traced { trace, .. } => trace,
+            _ => &[],
+        }
+    }
+
+    /// The source the error's span points into: the source of the function it was raised in,
+    /// or `source`, the script being run, if it wasn't raised inside a function
+    pub fn raised_in<'a>(&'a self, source: &'a str) -> &'a str {
+        match self.trace().first() {
+            Some(frame) => &frame.source,
+            None => source,
+        }
+    }
+
+    /// Record that the error escaped some more calls, given innermost first
+    pub(crate) fn escaped(self, frames: impl IntoIterator<Item = TraceFrame>) -> Error {
+        let mut frames = frames.into_iter().peekable();
+        if frames.peek().is_none() {
+            return self;
+        }
+        match self {
+            Error::Traced { error, mut trace } => {
+                trace.extend(frames);
+                Error::Traced { error, trace }
+            }
+            error => Error::Traced {
+                error: Box::new(error),
+                trace: frames.collect(),
+            },
+        }
+    }
+
+    /// The code the script asked to exit with, if this is the script calling `exit` rather
+    /// than something going wrong
+    pub fn exit_code(&self) -> Option<u8> {
+        match self {
+            Error::Exit { code, .. } => Some(*code),
+            Error::Traced { error, .. } => error.exit_code(),
+            _ => None,
+        }
+    }
+
+    /// Whether a `catch` block can handle this error. Failing to write output isn't the
+    /// script's fault, scripts mustn't be able to carry on past their limits, and `exit` has to
+    /// actually exit, so those always stop the script.
+    pub(crate) fn is_catchable(&self) -> bool {
+        match self {
+            Error::Output(..)
+            | Error::StepLimitExceeded { .. }
+            | Error::TimedOut { .. }
+            | Error::CollectionTooLarge { .. }
+            | Error::Cancelled(_)
+            | Error::Exit { .. } => false,
+            Error::Traced { error, .. } => error.is_catchable(),
+            _ => true,
+        }
+    }
+
+    pub fn message(&self, source: &str) -> String {
+        match self {
+            Error::Type(TypeError { expected, got, .. }) => {
+                format!("Type Error: expected {}, got {}", expected, got)
+            }
+            Error::VariableDoesntExist { .. } => "Variable doesn't exist".into(),
+            Error::NotCallable(_) => "Value is not callable".into(),
+            Error::OnlyInstancesHaveProperties(_) => "Only instances have properties".into(),
+            Error::UndefinedProperty(name) => {
+                format!("Undefined property '{}'", name.span.slice(source))
+            }
+            Error::SuperclassMustBeAClass(_) => "Superclass must be a class".into(),
+            Error::ClassInheritsFromItself(_) => "A class can't inherit from itself".into(),
+            Error::Arity { got, expected, .. } => format!(
+                "Wrong number of call arguments. Expected {}, but got {}",
+                expected, got
             ),
-            Error::VariableDoesntExist(token) => {
-                lexer::Error::display_error(source, &token.span, "Variable doesn't exist")
+            Error::Output(error, _) => format!("Couldn't write output: {error}"),
+            Error::NotIndexable(_) => "Only lists and maps can be indexed".into(),
+            Error::IndexOutOfRange { index, length, .. } => {
+                format!("Index {index} is out of range for a list of length {length}")
+            }
+            Error::IndexNotWholeNumber(_) => "Index must be a whole number".into(),
+            Error::PopFromEmptyList(_) => "Can't pop from an empty list".into(),
+            Error::UnhashableKey { type_name, .. } => {
+                format!("Can't use a value of type {type_name} as a map key")
+            }
+            Error::NotIterable(_) => "Only lists and maps can be iterated over".into(),
+            Error::StackOverflow(_) => "Stack overflow".into(),
+            Error::StepLimitExceeded { limit, .. } => format!("Step limit of {limit} exceeded"),
+            Error::TimedOut { timeout, .. } => format!("Timed out after {timeout:?}"),
+            Error::CollectionTooLarge { limit, .. } => {
+                format!("Collection size limit of {limit} exceeded")
             }
-            Error::NotCallable(name_span) => {
-                lexer::Error::display_error(source, name_span, "Value is not callable")
+            Error::Cancelled(_) => "Cancelled".into(),
+            Error::Exit { code, .. } => format!("Exited with code {code}"),
+            Error::InvalidExitCode { code, .. } => {
+                format!("Exit code must be a whole number from 0 to 255, got {code}")
             }
+            Error::Thrown(Thrown { message, .. }) => message.clone(),
+            Error::Traced { error, .. } => error.message(self.raised_in(source)),
+        }
+    }
+
+    /// A stable identifier for the kind of error, for tools to match on
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Type(_) => "RUNTIME001",
+            Error::VariableDoesntExist { .. } => "RUNTIME002",
+            Error::NotCallable(_) => "RUNTIME003",
+            Error::OnlyInstancesHaveProperties(_) => "RUNTIME004",
+            Error::UndefinedProperty(_) => "RUNTIME005",
+            Error::SuperclassMustBeAClass(_) => "RUNTIME006",
+            Error::ClassInheritsFromItself(_) => "RUNTIME007",
+            Error::Arity { .. } => "RUNTIME008",
+            Error::Output(..) => "RUNTIME009",
+            Error::NotIndexable(_) => "RUNTIME010",
+            Error::IndexOutOfRange { .. } => "RUNTIME011",
+            Error::PopFromEmptyList(_) => "RUNTIME012",
+            Error::UnhashableKey { .. } => "RUNTIME013",
+            Error::NotIterable(_) => "RUNTIME014",
+            Error::StackOverflow(_) => "RUNTIME015",
+            Error::StepLimitExceeded { .. } => "RUNTIME016",
+            Error::TimedOut { .. } => "RUNTIME017",
+            Error::CollectionTooLarge { .. } => "RUNTIME018",
+            Error::Cancelled(_) => "RUNTIME019",
+            Error::Thrown(_) => "RUNTIME020",
+            Error::Exit { .. } => "RUNTIME021",
+            Error::InvalidExitCode { .. } => "RUNTIME022",
+            Error::IndexNotWholeNumber(_) => "RUNTIME023",
+            Error::Traced { error, .. } => error.code(),
+        }
+    }
+
+    pub fn diagnostic(&self, source: &str) -> Diagnostic {
+        let diagnostic = Diagnostic::error(self.code(), self.message(source), self.span());
+        match self {
             Error::Arity {
-                got,
                 expected,
-                call_span,
-            } => lexer::Error::display_error(
-                source,
-                call_span,
-                &format!(
-                    "Wrong number of call arguments. Expected {}, but got {}",
-                    expected, got
+                declaration_span: Some(declaration_span),
+                ..
+            } => diagnostic.with_label(
+                *declaration_span,
+                format!(
+                    "function declared here with {expected} parameter{}",
+                    if *expected == 1 { "" } else { "s" }
                 ),
             ),
+            Error::VariableDoesntExist {
+                suggestion: Some(suggestion),
+                ..
+            } => diagnostic.with_help(format!("did you mean `{suggestion}`?")),
+            Error::Traced { error, .. } => error.diagnostic(self.raised_in(source)),
+            _ => diagnostic,
+        }
+    }
+
+    pub fn display(&self, source: &str, output: &mut dyn Write) -> io::Result<()> {
+        self.display_with(&SourceMap::new(source), Color::Never, output)
+    }
+
+    pub fn display_with(
+        &self,
+        source_map: &SourceMap,
+        color: Color,
+        output: &mut dyn Write,
+    ) -> io::Result<()> {
+        // Errors raised inside functions declared by an earlier run are shown in that run's
+        // source
+        let raised_in = self.raised_in(source_map.source());
+        let raised_in_map;
+        let error_map = if raised_in == source_map.source() {
+            source_map
+        } else {
+            raised_in_map = SourceMap::new(raised_in);
+            &raised_in_map
+        };
+        self.diagnostic(source_map.source())
+            .render(error_map, color, output)?;
+        if let Error::Traced { trace, .. } = self {
+            // Each call happened on the line the call inside it was made from
+            let mut line = error_map.location(self.span().start).line;
+            let mut lines = vec![];
+            for (index, frame) in trace.iter().enumerate() {
+                let function = match frame.function.as_str() {
+                    "" => "anonymous function",
+                    name => name,
+                };
+                // The call was made by the function called next in the trace, or the script
+                let caller_source = match trace.get(index + 1) {
+                    Some(caller) => &caller.source,
+                    None => source_map.source(),
+                };
+                let call_line = if caller_source == source_map.source() {
+                    source_map.location(frame.call_span.start).line
+                } else {
+                    frame.call_span.line(caller_source)
+                };
+                lines.push(format!(
+                    "in {function} at line {line}, called from line {call_line}"
+                ));
+                line = call_line;
+            }
+            // Recursion repeats the same line many times, so only show it once
+            for group in lines.chunk_by(|a, b| a == b) {
+                writeln!(output, "  {}", group[0])?;
+                if group.len() > 1 {
+                    writeln!(output, "  ... repeated {} more times", group.len() - 1)?;
+                }
+            }
+            writeln!(output)?;
         }
+        Ok(())
     }
 }
@@ -0,0 +1,234 @@
+//! Functions implemented in Rust that are available to every Lox program, and the methods of
+//! built in types
+
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    limits::check_size,
+    map::{Map, MapKey},
+    span::Span,
+};
+
+use super::{
+    error::{Error, ErrorValue},
+    value::{NativeFunction, NativeFunctionImplementation, Value},
+};
+
+/// The number of seconds since the Unix epoch
+pub(crate) fn clock(_arguments: &[Rc<Value>], call_span: Span) -> Result<Rc<Value>, Error> {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs_f64())
+        .unwrap_or(0.0);
+    Ok(Rc::new(Value::Number(call_span, seconds)))
+}
+
+/// Stop the script, exiting the process with the given code if it's being run from the command
+/// line
+pub(crate) fn exit(arguments: &[Rc<Value>], call_span: Span) -> Result<Rc<Value>, Error> {
+    let Value::Number(_, code) = *arguments[0] else {
+        return Err(Error::type_error(
+            "Number".into(),
+            arguments[0].string_description(),
+            arguments[0].span(),
+        ));
+    };
+    Err(Error::Exit {
+        code: checked_exit_code(code, call_span)?,
+        span: call_span,
+    })
+}
+
+/// Check a number is a whole number that can be used as a process's exit code
+pub(crate) fn checked_exit_code(code: f64, span: Span) -> Result<u8, Error> {
+    if !(0.0..=255.0).contains(&code) || code.fract() != 0.0 {
+        return Err(Error::InvalidExitCode { code, span });
+    }
+    Ok(code as u8)
+}
+
+/// Check a value can be used to index into a list of the given length, returning the index
+pub(crate) fn list_index(index: &Value, length: usize, span: Span) -> Result<usize, Error> {
+    checked_index(index_number(index, span)?, length, span)
+}
+
+fn index_number(index: &Value, span: Span) -> Result<f64, Error> {
+    match *index {
+        Value::Number(_, index) => Ok(index),
+        _ => Err(Error::type_error(
+            "Number".into(),
+            index.string_description(),
+            span,
+        )),
+    }
+}
+
+/// Check a number is a whole number that can index into a list of the given length. Both
+/// backends index lists through this, so they agree on which error a bad index gets.
+pub(crate) fn checked_index(index: f64, length: usize, span: Span) -> Result<usize, Error> {
+    if index.fract() != 0.0 {
+        return Err(Error::IndexNotWholeNumber(span));
+    }
+    if index < 0.0 || index >= length as f64 {
+        return Err(Error::IndexOutOfRange {
+            index,
+            length,
+            span,
+        });
+    }
+    Ok(index as usize)
+}
+
+/// Check a number is a whole number a new element can be inserted at in a list of the given
+/// length. Inserting just past the end is the same as pushing.
+pub(crate) fn checked_insert_index(index: f64, length: usize, span: Span) -> Result<usize, Error> {
+    if index == length as f64 {
+        return Ok(length);
+    }
+    checked_index(index, length, span)
+}
+
+/// Look up one of the methods every list has, bound to the given list. Lists can't grow past
+/// `max_size` elements.
+pub(crate) fn list_method(
+    list: &Rc<RefCell<Vec<Rc<Value>>>>,
+    name: &str,
+    max_size: Option<usize>,
+) -> Option<NativeFunction> {
+    let list = list.clone();
+    let (arity, function): (usize, Rc<NativeFunctionImplementation>) = match name {
+        "push" => (
+            1,
+            Rc::new(move |arguments, call_span| {
+                check_size(max_size, list.borrow().len() + 1, call_span)?;
+                list.borrow_mut().push(arguments[0].clone());
+                Ok(Rc::new(Value::Nil(call_span)))
+            }),
+        ),
+        "pop" => (
+            0,
+            Rc::new(move |_, call_span| {
+                list.borrow_mut()
+                    .pop()
+                    .ok_or(Error::PopFromEmptyList(call_span))
+            }),
+        ),
+        "len" => (
+            0,
+            Rc::new(move |_, call_span| {
+                Ok(Rc::new(Value::Number(
+                    call_span,
+                    list.borrow().len() as f64,
+                )))
+            }),
+        ),
+        "insert" => (
+            2,
+            Rc::new(move |arguments, call_span| {
+                let mut list = list.borrow_mut();
+                let index = index_number(&arguments[0], call_span)?;
+                let index = checked_insert_index(index, list.len(), call_span)?;
+                check_size(max_size, list.len() + 1, call_span)?;
+                list.insert(index, arguments[1].clone());
+                Ok(Rc::new(Value::Nil(call_span)))
+            }),
+        ),
+        "remove" => (
+            1,
+            Rc::new(move |arguments, call_span| {
+                let mut list = list.borrow_mut();
+                let index = list_index(&arguments[0], list.len(), call_span)?;
+                Ok(list.remove(index))
+            }),
+        ),
+        _ => return None,
+    };
+
+    Some(NativeFunction {
+        name: name.into(),
+        arity,
+        function,
+    })
+}
+
+/// The key a value is stored under in a map, or an error if it can't be used as a key
+pub(crate) fn map_key(key: &Value, span: Span) -> Result<MapKey, Error> {
+    key.to_map_key().ok_or_else(|| Error::UnhashableKey {
+        type_name: key.string_description(),
+        span,
+    })
+}
+
+/// Look up one of the methods every map has, bound to the given map
+pub(crate) fn map_method(map: &Rc<RefCell<Map<Rc<Value>>>>, name: &str) -> Option<NativeFunction> {
+    let map = map.clone();
+    let (arity, function): (usize, Rc<NativeFunctionImplementation>) = match name {
+        "has" => (
+            1,
+            Rc::new(move |arguments, call_span| {
+                let key = map_key(&arguments[0], call_span)?;
+                Ok(Rc::new(Value::Boolean(
+                    call_span,
+                    map.borrow().contains_key(&key),
+                )))
+            }),
+        ),
+        "remove" => (
+            1,
+            Rc::new(move |arguments, call_span| {
+                let key = map_key(&arguments[0], call_span)?;
+                Ok(map
+                    .borrow_mut()
+                    .remove(&key)
+                    .unwrap_or_else(|| Rc::new(Value::Nil(call_span))))
+            }),
+        ),
+        "len" => (
+            0,
+            Rc::new(move |_, call_span| {
+                Ok(Rc::new(Value::Number(call_span, map.borrow().len() as f64)))
+            }),
+        ),
+        "keys" => (
+            0,
+            Rc::new(move |_, call_span| {
+                let keys = map
+                    .borrow()
+                    .keys()
+                    .map(|key| Rc::new(Value::from_map_key(key, call_span)))
+                    .collect();
+                Ok(Rc::new(Value::List(call_span, Rc::new(RefCell::new(keys)))))
+            }),
+        ),
+        "values" => (
+            0,
+            Rc::new(move |_, call_span| {
+                let values = map.borrow().values().cloned().collect();
+                Ok(Rc::new(Value::List(
+                    call_span,
+                    Rc::new(RefCell::new(values)),
+                )))
+            }),
+        ),
+        _ => return None,
+    };
+
+    Some(NativeFunction {
+        name: name.into(),
+        arity,
+        function,
+    })
+}
+
+/// Read the `message` or `line` of a caught runtime error
+pub(crate) fn error_property(error: &ErrorValue, name: &str, span: Span) -> Option<Value> {
+    match name {
+        "message" => Some(Value::String(span, error.message.clone())),
+        "line" => Some(Value::Number(span, error.line as f64)),
+        _ => None,
+    }
+}
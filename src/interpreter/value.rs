@@ -1,8 +1,15 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
 
-use crate::{span::Span, statement::Declaration};
+use crate::{
+    map::{Map, MapKey},
+    span::Span,
+    statement::Declaration,
+};
 
-use super::environment::Environment;
+use super::{
+    environment::Environment,
+    error::{Error, ErrorValue},
+};
 
 #[derive(Debug, Clone)]
 pub enum Value {
@@ -11,34 +18,254 @@ pub enum Value {
     Boolean(Span, bool),
     Nil(Span),
     Callable(Callable),
+    NativeFunction(Span, NativeFunction),
+    Class(Rc<Class>),
+    Instance(Span, Rc<RefCell<Instance>>),
+    List(Span, Rc<RefCell<Vec<Rc<Value>>>>),
+    Map(Span, Rc<RefCell<Map<Rc<Value>>>>),
+    /// A runtime error caught by a `catch` block
+    Error(Span, Rc<ErrorValue>),
 }
 
 #[derive(Debug, Clone)]
 pub struct Callable {
     pub environment: Rc<RefCell<Environment>>,
+    /// The source the callable was declared in
+    pub source: Rc<str>,
+    pub name: String,
     pub name_span: Span,
     pub parameters: Vec<String>,
     pub body: Rc<Vec<Declaration>>,
+    pub is_initialiser: bool,
+}
+
+impl Callable {
+    /// Create a copy of this callable whose environment has `this` bound to the given instance
+    pub(crate) fn bind(&self, instance: Rc<Value>) -> Callable {
+        let mut environment = Environment::close_over(self.environment.clone());
+        environment.define("this".into(), instance);
+        Callable {
+            environment: Rc::new(RefCell::new(environment)),
+            ..self.clone()
+        }
+    }
+}
+
+/// The Rust implementation of a native function. Receives the evaluated arguments and the span
+/// of the call expression.
+pub type NativeFunctionImplementation = dyn Fn(&[Rc<Value>], Span) -> Result<Rc<Value>, Error>;
+
+/// A function implemented in Rust that can be called from Lox
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: usize,
+    pub function: Rc<NativeFunctionImplementation>,
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+pub struct Class {
+    pub name: String,
+    pub name_span: Span,
+    pub superclass: Option<Rc<Class>>,
+    pub methods: HashMap<String, Callable>,
+}
+
+impl Class {
+    /// Look up a method on this class, falling back to the inheritance chain
+    pub(crate) fn find_method(&self, name: &str) -> Option<&Callable> {
+        self.methods.get(name).or_else(|| {
+            self.superclass
+                .as_ref()
+                .and_then(|superclass| superclass.find_method(name))
+        })
+    }
+
+    /// The number of arguments a call to this class expects, which is the arity of its
+    /// initialiser if it has one
+    pub(crate) fn arity(&self) -> usize {
+        self.find_method("init")
+            .map(|initialiser| initialiser.parameters.len())
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub class: Rc<Class>,
+    pub fields: HashMap<String, Rc<Value>>,
+}
+
+impl Instance {
+    pub(crate) fn new(class: Rc<Class>) -> Self {
+        Self {
+            class,
+            fields: HashMap::new(),
+        }
+    }
 }
 
 impl Value {
+    /// Lox equality. Values of different types are never equal, and functions, classes and
+    /// instances are only equal to themselves.
+    pub(crate) fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::String(_, left), Value::String(_, right)) => left == right,
+            (Value::Number(_, left), Value::Number(_, right)) => left == right,
+            (Value::Boolean(_, left), Value::Boolean(_, right)) => left == right,
+            (Value::Nil(_), Value::Nil(_)) => true,
+            (Value::Callable(left), Value::Callable(right)) => {
+                Rc::ptr_eq(&left.body, &right.body)
+                    && Rc::ptr_eq(&left.environment, &right.environment)
+            }
+            (Value::NativeFunction(_, left), Value::NativeFunction(_, right)) => {
+                Rc::ptr_eq(&left.function, &right.function)
+            }
+            (Value::Class(left), Value::Class(right)) => Rc::ptr_eq(left, right),
+            (Value::Instance(_, left), Value::Instance(_, right)) => Rc::ptr_eq(left, right),
+            (Value::List(_, left), Value::List(_, right)) => Rc::ptr_eq(left, right),
+            (Value::Map(_, left), Value::Map(_, right)) => Rc::ptr_eq(left, right),
+            (Value::Error(_, left), Value::Error(_, right)) => Rc::ptr_eq(left, right),
+            _ => false,
+        }
+    }
+
+    /// The key this value is stored under in a map, if it can be used as a key
+    pub(crate) fn to_map_key(&self) -> Option<MapKey> {
+        match self {
+            Value::String(_, string) => Some(MapKey::String(string.clone())),
+            Value::Number(_, number) => Some(MapKey::number(*number)),
+            Value::Boolean(_, boolean) => Some(MapKey::Boolean(*boolean)),
+            Value::Nil(_) => Some(MapKey::Nil),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn from_map_key(key: &MapKey, span: Span) -> Value {
+        match key {
+            MapKey::String(string) => Value::String(span, string.clone()),
+            MapKey::Number(bits) => Value::Number(span, f64::from_bits(*bits)),
+            MapKey::Boolean(boolean) => Value::Boolean(span, *boolean),
+            MapKey::Nil => Value::Nil(span),
+        }
+    }
+
+    pub(crate) fn string_description(&self) -> String {
+        match self {
+            Value::String(_, _) => "String".into(),
+            Value::Number(_, _) => "Number".into(),
+            Value::Boolean(_, _) => "Boolean".into(),
+            Value::Nil(_) => "Nil".into(),
+            Value::Callable { .. } => "Callable".into(),
+            Value::NativeFunction(..) => "Callable".into(),
+            Value::Class(_) => "Class".into(),
+            Value::Instance(_, _) => "Instance".into(),
+            Value::List(_, _) => "List".into(),
+            Value::Map(_, _) => "Map".into(),
+            Value::Error(_, _) => "Error".into(),
+        }
+    }
+
     pub(crate) fn span(&self) -> Span {
         *match self {
             Value::String(span, _) => span,
             Value::Number(span, _) => span,
             Value::Boolean(span, _) => span,
             Value::Nil(span) => span,
-            Value::Callable(_) => todo!(),
+            Value::Callable(callable) => &callable.name_span,
+            // Native functions aren't declared anywhere in the source, so this is where it's used
+            Value::NativeFunction(span, _) => span,
+            Value::Class(class) => &class.name_span,
+            Value::Instance(span, _) => span,
+            Value::List(span, _) => span,
+            Value::Map(span, _) => span,
+            Value::Error(span, _) => span,
+        }
+    }
+}
+
+/// Print a string as a Lox string literal, quoted and escaped so it reads back as the same
+/// string. Shared by both backends for strings inside lists and maps.
+pub(crate) fn fmt_quoted(string: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "\"")?;
+    let mut characters = string.chars().peekable();
+    while let Some(character) = characters.next() {
+        match character {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            '\r' => write!(f, "\\r")?,
+            '\0' => write!(f, "\\0")?,
+            // Only `${` starts an interpolation
+            '$' if characters.peek() == Some(&'{') => write!(f, "\\$")?,
+            character => write!(f, "{character}")?,
         }
     }
+    write!(f, "\"")
+}
+
+thread_local! {
+    /// The lists and maps currently being printed, innermost last
+    static PRINTING: RefCell<Vec<*const ()>> = const { RefCell::new(vec![]) };
+}
+
+/// Print a list or map with `fmt`, unless it's already being printed because it contains
+/// itself, in which case `placeholder` is printed instead of recursing forever. Shared by both
+/// backends.
+pub(crate) fn fmt_collection(
+    collection: *const (),
+    placeholder: &str,
+    f: &mut fmt::Formatter<'_>,
+    fmt: impl FnOnce(&mut fmt::Formatter<'_>) -> fmt::Result,
+) -> fmt::Result {
+    if PRINTING.with_borrow(|printing| printing.contains(&collection)) {
+        return write!(f, "{placeholder}");
+    }
+    PRINTING.with_borrow_mut(|printing| printing.push(collection));
+    let result = fmt(f);
+    PRINTING.with_borrow_mut(|printing| printing.pop());
+    result
+}
 
-    pub(crate) fn pretty_print(&self) {
+/// Strings are printed as they are, unless the alternate flag (`{:#}`) is given, which quotes
+/// them. Lists and maps print their elements that way so they can't be confused with other values.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Value::String(_, string) => println!("{string}"),
-            Value::Number(_, number) => println!("{number}"),
-            Value::Boolean(_, boolean) => println!("{boolean}"),
-            Value::Nil(_) => println!("nil"),
-            Value::Callable(_) => todo!(),
+            Value::String(_, string) if f.alternate() => fmt_quoted(string, f),
+            Value::String(_, string) => write!(f, "{string}"),
+            Value::Number(_, number) => write!(f, "{number}"),
+            Value::Boolean(_, boolean) => write!(f, "{boolean}"),
+            Value::Nil(_) => write!(f, "nil"),
+            Value::Callable(callable) if callable.name.is_empty() => write!(f, "<fn>"),
+            Value::Callable(callable) => write!(f, "<fn {}>", callable.name),
+            Value::NativeFunction(..) => write!(f, "<native fn>"),
+            Value::Class(class) => write!(f, "{}", class.name),
+            Value::Instance(_, instance) => write!(f, "{} instance", instance.borrow().class.name),
+            Value::List(_, list) => fmt_collection(Rc::as_ptr(list).cast(), "[...]", f, |f| {
+                write!(f, "[")?;
+                for (index, element) in list.borrow().iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{element:#}")?;
+                }
+                write!(f, "]")
+            }),
+            Value::Map(_, map) => fmt_collection(Rc::as_ptr(map).cast(), "{...}", f, |f| {
+                write!(f, "{}", map.borrow())
+            }),
+            Value::Error(_, error) => write!(f, "{error}"),
         }
     }
 }
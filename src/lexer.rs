@@ -1,12 +1,29 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
 
-use crate::span::Span;
+use crate::{
+    diagnostic::{Color, Diagnostic},
+    source_map::SourceMap,
+    span::Span,
+};
 
 pub struct Lexer<'a> {
     source: &'a str,
     current_position: usize,
     errors: Vec<Error>,
     tokens: Vec<Token>,
+    /// The `${` interpolations we're currently inside, innermost last
+    interpolations: Vec<OpenInterpolation>,
+}
+
+struct OpenInterpolation {
+    /// Where the string containing the interpolation starts, for reporting it if it's unterminated
+    string_start: usize,
+    /// How many `{` inside the interpolation haven't been closed yet. The `}` that closes the
+    /// interpolation itself resumes the string.
+    open_braces: usize,
 }
 
 impl<'a> Lexer<'a> {
@@ -16,6 +33,7 @@ impl<'a> Lexer<'a> {
             current_position: 0,
             errors: vec![],
             tokens: vec![],
+            interpolations: vec![],
         };
 
         loop {
@@ -31,8 +49,14 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /// The character at the current position. Positions are byte offsets, so they can index
+    /// into the source directly.
     fn current_character(&self) -> Option<char> {
-        self.source.chars().nth(self.current_position)
+        self.source[self.current_position..].chars().next()
+    }
+
+    fn next_character(&self) -> Option<char> {
+        self.source[self.current_position..].chars().nth(1)
     }
 
     fn absorb_single_character_token(&mut self, token_type: TokenType) {
@@ -50,7 +74,7 @@ impl<'a> Lexer<'a> {
     fn absorb_if_match(&mut self, character_to_match: char) -> bool {
         match self.current_character() {
             Some(current_character) if current_character == character_to_match => {
-                self.current_position += 1;
+                self.current_position += character_to_match.len_utf8();
                 true
             }
             _ => false,
@@ -77,8 +101,11 @@ impl<'a> Lexer<'a> {
                 Some('=') => {
                     self.current_position += 1;
                     let is_equal_equal = self.absorb_if_match('=');
+                    let is_arrow = !is_equal_equal && self.absorb_if_match('>');
                     self.tokens.push(if is_equal_equal {
                         Token::new(self.current_position - 2, self.current_position, EqualEqual)
+                    } else if is_arrow {
+                        Token::new(self.current_position - 2, self.current_position, Arrow)
                     } else {
                         Token::new(self.current_position - 1, self.current_position, Equal)
                     });
@@ -108,6 +135,31 @@ impl<'a> Lexer<'a> {
                     });
                     return NextResult::NotDone;
                 }
+                Some('{') => {
+                    if let Some(interpolation) = self.interpolations.last_mut() {
+                        interpolation.open_braces += 1;
+                    }
+                    self.absorb_single_character_token(LeftBrace);
+                    return NextResult::NotDone;
+                }
+                Some('}') => {
+                    match self.interpolations.last_mut() {
+                        Some(interpolation) if interpolation.open_braces == 0 => {
+                            let interpolation = self.interpolations.pop().unwrap();
+                            self.absorb_single_character_token(RightBrace);
+                            self.lex_string_segment(
+                                self.current_position,
+                                interpolation.string_start,
+                            );
+                        }
+                        Some(interpolation) => {
+                            interpolation.open_braces -= 1;
+                            self.absorb_single_character_token(RightBrace);
+                        }
+                        None => self.absorb_single_character_token(RightBrace),
+                    }
+                    return NextResult::NotDone;
+                }
                 Some('/') => {
                     self.current_position += 1;
                     let is_comment = self.absorb_if_match('/');
@@ -156,9 +208,17 @@ impl<'a> Lexer<'a> {
                     self.errors.push(Error::UnexpectedToken {
                         at: self.current_position,
                     });
-                    self.current_position += 1;
+                    self.current_position += character.len_utf8();
                 }
                 None => {
+                    // The strings around the innermost one only run to the end of the source
+                    // because it does, so it's the only one reported
+                    if let Some(interpolation) = self.interpolations.pop() {
+                        self.errors.push(Error::UnterminatedStringLiteral {
+                            starting_at: interpolation.string_start,
+                        });
+                    }
+                    self.interpolations.clear();
                     self.tokens.push(Token::new(
                         self.current_position,
                         self.current_position + 1,
@@ -176,19 +236,26 @@ impl<'a> Lexer<'a> {
 
         let keywords: HashMap<&'static str, TokenType> = [
             ("and", TokenType::And),
+            ("break", TokenType::Break),
+            ("catch", TokenType::Catch),
             ("class", TokenType::Class),
+            ("continue", TokenType::Continue),
             ("else", TokenType::Else),
             ("false", TokenType::False),
+            ("finally", TokenType::Finally),
             ("for", TokenType::For),
             ("fun", TokenType::Fun),
             ("if", TokenType::If),
+            ("in", TokenType::In),
             ("nil", TokenType::Nil),
             ("or", TokenType::Or),
             ("print", TokenType::Print),
             ("return", TokenType::Return),
             ("super", TokenType::Super),
             ("this", TokenType::This),
+            ("throw", TokenType::Throw),
             ("true", TokenType::True),
+            ("try", TokenType::Try),
             ("var", TokenType::Var),
             ("while", TokenType::While),
         ]
@@ -231,7 +298,7 @@ impl<'a> Lexer<'a> {
             current_character = self.current_character();
         }
 
-        let next_character = self.source.chars().nth(self.current_position + 1);
+        let next_character = self.next_character();
         if current_character == Some('.')
             && next_character.is_some()
             && is_digit(next_character.unwrap())
@@ -251,37 +318,124 @@ impl<'a> Lexer<'a> {
         ));
     }
 
+    /// Strings can span multiple lines. Escape sequences are decoded into the token's literal
+    /// value.
     fn lex_string(&mut self) {
         let string_start = self.current_position;
         assert!(self.absorb_if_match('"'));
-        let mut current_character = self.current_character();
+        self.lex_string_segment(string_start, string_start);
+    }
 
-        while current_character.is_some() && current_character != Some('"') {
-            self.current_position += 1;
-            current_character = self.current_character();
-        }
+    /// Lex the text of a string up to its closing quote, or up to the next `${`. Interpolated
+    /// strings are lexed as an `Interpolation` token for the text before each `${`, the tokens of
+    /// each embedded expression followed by a `RightBrace`, and a `String_` token for the text
+    /// after the last `}`.
+    fn lex_string_segment(&mut self, segment_start: usize, string_start: usize) {
+        let mut value = String::new();
 
-        if current_character.is_none() {
-            self.errors.push(Error::UnterminatedStringLiteral {
-                starting_at: string_start,
-            });
-            return;
+        loop {
+            match self.current_character() {
+                None => {
+                    self.errors.push(Error::UnterminatedStringLiteral {
+                        starting_at: string_start,
+                    });
+                    // Any strings this one is interpolated into are unterminated because of it
+                    self.interpolations.clear();
+                    return;
+                }
+                Some('"') => break,
+                Some('$') if self.next_character() == Some('{') => {
+                    self.current_position += 2;
+                    self.tokens.push(Token::with_literal(
+                        segment_start,
+                        self.current_position,
+                        TokenType::Interpolation,
+                        value,
+                    ));
+                    self.interpolations.push(OpenInterpolation {
+                        string_start,
+                        open_braces: 0,
+                    });
+                    return;
+                }
+                Some('\\') => {
+                    if let Some(character) = self.lex_escape_sequence() {
+                        value.push(character);
+                    }
+                }
+                Some(character) => {
+                    value.push(character);
+                    self.current_position += character.len_utf8();
+                }
+            }
         }
 
         assert!(self.absorb_if_match('"'));
-        self.tokens.push(Token::new(
-            string_start,
+        self.tokens.push(Token::with_literal(
+            segment_start,
             self.current_position,
             TokenType::String_,
+            value,
         ));
     }
 
+    /// Decode the escape sequence starting at the current backslash. Invalid escape sequences are
+    /// reported and skipped.
+    fn lex_escape_sequence(&mut self) -> Option<char> {
+        let escape_start = self.current_position;
+        assert!(self.absorb_if_match('\\'));
+        // A backslash at the end of the source is reported as an unterminated string instead
+        let escaped = self.current_character()?;
+        self.current_position += escaped.len_utf8();
+
+        let character = match escaped {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            'r' => Some('\r'),
+            '0' => Some('\0'),
+            '"' => Some('"'),
+            '$' => Some('$'),
+            '\\' => Some('\\'),
+            'u' => self.lex_unicode_escape(),
+            _ => None,
+        };
+        if character.is_none() {
+            self.errors.push(Error::InvalidEscapeSequence {
+                span: Span::new(escape_start, self.current_position),
+            });
+        }
+        character
+    }
+
+    /// Decode the `{1F600}` part of a `\u{1F600}` escape sequence, which can have between one and
+    /// six hex digits
+    fn lex_unicode_escape(&mut self) -> Option<char> {
+        if !self.absorb_if_match('{') {
+            return None;
+        }
+        let digits_start = self.current_position;
+        while self
+            .current_character()
+            .is_some_and(|character| character.is_ascii_hexdigit())
+        {
+            self.current_position += 1;
+        }
+        let digits = &self.source[digits_start..self.current_position];
+
+        if !self.absorb_if_match('}') || digits.is_empty() || digits.len() > 6 {
+            return None;
+        }
+        u32::from_str_radix(digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+    }
+
     /// Ignore the rest of the line
     fn absorb_until_newline(&mut self) {
-        let mut current_character = self.current_character();
-        while current_character.is_some() && current_character != Some('\n') {
-            self.current_position += 1;
-            current_character = self.current_character();
+        while let Some(character) = self.current_character()
+            && character != '\n'
+        {
+            self.current_position += character.len_utf8();
         }
     }
 }
@@ -309,6 +463,9 @@ pub struct Result {
 pub struct Token {
     pub span: Span,
     pub type_: TokenType,
+    /// The value of a string literal or interpolated string segment, with its escape sequences
+    /// decoded
+    pub literal: Option<String>,
 }
 
 impl Token {
@@ -316,6 +473,19 @@ impl Token {
         Self {
             type_,
             span: Span::new(span_start, span_end),
+            literal: None,
+        }
+    }
+
+    pub fn with_literal(
+        span_start: usize,
+        span_end: usize,
+        type_: TokenType,
+        value: String,
+    ) -> Self {
+        Self {
+            literal: Some(value),
+            ..Self::new(span_start, span_end, type_)
         }
     }
 }
@@ -327,6 +497,9 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
     Minus,
@@ -344,20 +517,26 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    Arrow,
 
     // Literals.
     Identifier,
     String_,
+    /// The part of an interpolated string before a `${`
+    Interpolation,
     Number,
 
     // Keywords.
     And,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     Fun,
     For,
     If,
+    In,
     Nil,
     Or,
     Print,
@@ -367,6 +546,10 @@ pub enum TokenType {
     True,
     Var,
     While,
+    Try,
+    Catch,
+    Finally,
+    Throw,
 
     Eof,
 }
@@ -379,6 +562,9 @@ impl TokenType {
             ')' => Some(RightParen),
             '{' => Some(LeftBrace),
             '}' => Some(RightBrace),
+            '[' => Some(LeftBracket),
+            ']' => Some(RightBracket),
+            ':' => Some(Colon),
             ',' => Some(Comma),
             '.' => Some(Dot),
             '-' => Some(Minus),
@@ -394,113 +580,65 @@ impl TokenType {
 pub enum Error {
     UnterminatedStringLiteral { starting_at: usize },
     UnexpectedToken { at: usize },
-}
-
-struct LinesForErrorDisplay {
-    pub line_before: Option<Span>,
-    pub line: Span,
-    // line_after: Span,
-    pub line_number_of_error: usize,
+    InvalidEscapeSequence { span: Span },
 }
 
 impl Error {
-    fn lines_for_error_display(source: &str, error_starts_at: usize) -> LinesForErrorDisplay {
-        // Index into the source the start of the current line
-        let mut previous_line_start = 0;
-        let mut current_line_start = 0;
-        let mut line_number = 1;
-        for (index, value) in source.chars().enumerate() {
-            if index == error_starts_at {
-                break;
-            };
-            if value == '\n' && index < source.len() - 1 {
-                previous_line_start = current_line_start;
-                current_line_start = index + 1;
-                line_number += 1;
-            };
+    pub fn span(&self, source: &str) -> Span {
+        match self {
+            Error::UnterminatedStringLiteral { starting_at } => Span::new(
+                *starting_at,
+                Self::index_of_first_new_line_after(source, *starting_at),
+            ),
+            Error::UnexpectedToken { at } => Span::new(
+                *at,
+                *at + source[*at..].chars().next().map_or(1, char::len_utf8),
+            ),
+            Error::InvalidEscapeSequence { span } => *span,
         }
-        let next_new_line = {
-            let mut i = error_starts_at;
-            let mut current_char = source.chars().nth(i);
-            while current_char.is_some() && current_char != Some('\n') {
-                i += 1;
-                current_char = source.chars().nth(i);
-            }
-            i
-        };
-
-        let line_before = {
-            if line_number == 1 {
-                None
-            } else {
-                Some(Span::new(previous_line_start, current_line_start))
-            }
-        };
+    }
 
-        LinesForErrorDisplay {
-            line_before,
-            line: Span::new(current_line_start, next_new_line),
-            line_number_of_error: line_number,
+    pub fn message(&self) -> String {
+        match self {
+            Error::UnterminatedStringLiteral { .. } => "Unterminated String Literal",
+            Error::UnexpectedToken { .. } => "Unexpected token",
+            Error::InvalidEscapeSequence { .. } => "Invalid escape sequence",
         }
+        .into()
     }
 
-    pub fn display(&self, source: &str) {
+    /// A stable identifier for the kind of error, for tools to match on
+    pub fn code(&self) -> &'static str {
         match self {
-            Error::UnterminatedStringLiteral { starting_at } => Self::display_error(
-                source,
-                &Span::new(
-                    *starting_at,
-                    Self::index_of_first_new_line_after(source, *starting_at),
-                ),
-                "Unterminated String Literal",
-            ),
-            Error::UnexpectedToken { at } => {
-                Self::display_error(source, &Span::new(*at, *at + 1), "Unexpected token")
-            }
+            Error::UnterminatedStringLiteral { .. } => "LEX001",
+            Error::UnexpectedToken { .. } => "LEX002",
+            Error::InvalidEscapeSequence { .. } => "LEX003",
         }
     }
 
-    /// Given some source and an index, return the index of the next newline after the given index in the source
-    fn index_of_first_new_line_after(source: &str, index: usize) -> usize {
-        let mut i = index;
-        let mut current_char = source.chars().nth(i);
-        while current_char.is_some() && current_char != Some('\n') {
-            i += 1;
-            current_char = source.chars().nth(i);
-        }
-        i
+    pub fn diagnostic(&self, source: &str) -> Diagnostic {
+        Diagnostic::error(self.code(), self.message(), self.span(source))
     }
 
-    pub(crate) fn display_error<'a>(source: &'a str, span: &Span, error: &'a str) {
-        let lines = Error::lines_for_error_display(source, span.start);
-
-        println!("\n  \x1b[31mError:\x1b[0m {}\n", error);
-        if let Some(line_before) = lines.line_before {
-            // FIXME: We may need padding here if the number of digits in `line_number - 1` is
-            // less than `line_number`
-            print!(
-                " \x1b[34m{}\x1b[0m |  {}",
-                lines.line_number_of_error - 1,
-                line_before.slice(source)
-            )
-        }
+    pub fn display(&self, source: &str, output: &mut dyn Write) -> io::Result<()> {
+        self.display_with(&SourceMap::new(source), Color::Never, output)
+    }
 
-        println!(
-            " \x1b[34m{}\x1b[0m |  {}",
-            lines.line_number_of_error,
-            lines.line.slice(source)
-        );
+    pub fn display_with(
+        &self,
+        source_map: &SourceMap,
+        color: Color,
+        output: &mut dyn Write,
+    ) -> io::Result<()> {
+        self.diagnostic(source_map.source())
+            .render(source_map, color, output)
+    }
 
-        // FIXME: The amount of padding here should be dependent on the width of `line_number`
-        println!(
-            "      \x1b[31m{}{}=== {}\x1b[0m",
-            (0..span.start - lines.line.start)
-                .map(|_| ' ')
-                .collect::<String>(),
-            (0..span.end - span.start).map(|_| '^').collect::<String>(),
-            error
-        );
-        println!();
+    /// Given some source and an index, return the index of the next newline after the given index in the source
+    fn index_of_first_new_line_after(source: &str, index: usize) -> usize {
+        source[index..]
+            .find('\n')
+            .map_or(source.len(), |offset| index + offset)
     }
 }
 
@@ -547,5 +685,95 @@ mod tests {
         assert_eq!(lex_result.tokens[1].type_, TokenType::Eof);
         assert_eq!(lex_result.tokens[0].span.start, 1);
         assert_eq!(lex_result.tokens[0].span.end, 8);
+        assert_eq!(lex_result.tokens[0].literal.as_deref(), Some("hello"));
+    }
+
+    #[test]
+    fn string_escapes() {
+        let lex_result = Lexer::lex(r#""a\tb\n\"\\\u{1F600}""#);
+        assert_eq!(lex_result.errors.len(), 0);
+        assert_eq!(
+            lex_result.tokens[0].literal.as_deref(),
+            Some("a\tb\n\"\\\u{1F600}")
+        );
+
+        let lex_result = Lexer::lex(r#""a\qb\u{110000}""#);
+        let spans = lex_result
+            .errors
+            .iter()
+            .map(|error| match error {
+                Error::InvalidEscapeSequence { span } => (span.start, span.end),
+                error => panic!("Unexpected error {error:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(spans, vec![(2, 4), (5, 15)]);
+        assert_eq!(lex_result.tokens[0].literal.as_deref(), Some("ab"));
+    }
+
+    #[test]
+    fn string_interpolation() {
+        let lex_result = Lexer::lex(r#""a${ {"b": "${c}"} }d""#);
+        assert_eq!(lex_result.errors.len(), 0);
+        let tokens = lex_result
+            .tokens
+            .iter()
+            .map(|token| (token.type_.clone(), token.literal.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            vec![
+                (TokenType::Interpolation, Some("a")),
+                (TokenType::LeftBrace, None),
+                (TokenType::String_, Some("b")),
+                (TokenType::Colon, None),
+                (TokenType::Interpolation, Some("")),
+                (TokenType::Identifier, None),
+                (TokenType::RightBrace, None),
+                (TokenType::String_, Some("")),
+                (TokenType::RightBrace, None),
+                (TokenType::RightBrace, None),
+                (TokenType::String_, Some("d")),
+                (TokenType::Eof, None),
+            ]
+        );
+
+        let lex_result = Lexer::lex(r#""a${b"#);
+        assert!(matches!(
+            lex_result.errors[..],
+            [Error::UnterminatedStringLiteral { starting_at: 0 }]
+        ));
+    }
+
+    #[test]
+    fn only_the_innermost_unterminated_interpolated_string_is_reported() {
+        let lex_result = Lexer::lex(r#""a${ "b"#);
+        assert!(matches!(
+            lex_result.errors[..],
+            [Error::UnterminatedStringLiteral { starting_at: 5 }]
+        ));
+
+        let lex_result = Lexer::lex(r#""a${ "b${c"#);
+        assert!(matches!(
+            lex_result.errors[..],
+            [Error::UnterminatedStringLiteral { starting_at: 5 }]
+        ));
+    }
+
+    #[test]
+    fn spans_are_byte_offsets() {
+        let source = "\"é😀\" ü x";
+        let lex_result = Lexer::lex(source);
+        assert_eq!(lex_result.tokens[0].span.slice(source), "\"é😀\"");
+        assert_eq!(lex_result.tokens[1].span.slice(source), "x");
+        assert_eq!(lex_result.errors[0].span(source).slice(source), "ü");
+    }
+
+    #[test]
+    fn unterminated_multi_line_string() {
+        let source = "\"one\ntwo";
+        let lex_result = Lexer::lex(source);
+        assert_eq!(lex_result.errors.len(), 1);
+        let span = lex_result.errors[0].span(source);
+        assert_eq!((span.start, span.end), (0, 4));
     }
 }
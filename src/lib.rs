@@ -0,0 +1,185 @@
+#![feature(let_chains)]
+
+//! An implementation of the Lox language from [Crafting Interpreters](https://www.craftinginterpreters.com/).
+//!
+//! The simplest way to run some Lox is [`run`]. To keep globals around between runs, for
+//! example in a REPL, create an [`Interpreter`] and call [`Interpreter::run`] on it.
+
+mod diagnostic;
+mod expression;
+mod interpreter;
+mod lexer;
+mod limits;
+mod map;
+mod parser;
+mod resolver;
+mod source_map;
+mod span;
+mod statement;
+mod vm;
+
+pub use diagnostic::{Color, Diagnostic, Label, Severity};
+pub use interpreter::{
+    error::{Error as RuntimeError, ErrorValue, TraceFrame},
+    value::Value,
+    with_stack, Interpreter,
+};
+pub use lexer::Error as LexerError;
+pub use limits::{CancellationHandle, Limits};
+pub use map::{Map, MapKey};
+pub use parser::Error as ParserError;
+pub use resolver::Error as ResolverError;
+pub use source_map::SourceMap;
+pub use span::Span;
+pub use vm::{disassembler::disassemble, Vm};
+
+use std::io::{self, Write};
+
+use lexer::{Lexer, Token, TokenType};
+use parser::Parser;
+use resolver::Resolver;
+use statement::Declaration;
+
+/// Everything that can go wrong between reading some source and finishing running it
+#[derive(Debug)]
+pub enum Error {
+    /// Everything wrong with how the source is written, in the order it appears in the source
+    Syntax(Vec<SyntaxError>),
+    Resolver(Vec<ResolverError>),
+    Runtime(RuntimeError),
+}
+
+impl Error {
+    /// Every problem the error is made up of, in a form tools can consume
+    pub fn diagnostics(&self, source: &str) -> Vec<Diagnostic> {
+        match self {
+            Error::Syntax(errors) => errors.iter().map(|e| e.diagnostic(source)).collect(),
+            Error::Resolver(errors) => errors.iter().map(ResolverError::diagnostic).collect(),
+            Error::Runtime(error) => vec![error.diagnostic(source)],
+        }
+    }
+
+    /// The code the script asked to exit with, if it stopped by calling `exit`
+    pub fn exit_code(&self) -> Option<u8> {
+        match self {
+            Error::Runtime(error) => error.exit_code(),
+            _ => None,
+        }
+    }
+
+    /// Render the error without colour, pointing at the offending parts of `source`, into
+    /// `output`
+    pub fn display(&self, source: &str, output: &mut dyn Write) -> io::Result<()> {
+        self.display_with(&SourceMap::new(source), Color::Never, output)
+    }
+
+    /// Render the error like [`Error::display`], describing where each part of it is with
+    /// `source_map`
+    pub fn display_with(
+        &self,
+        source_map: &SourceMap,
+        color: Color,
+        output: &mut dyn Write,
+    ) -> io::Result<()> {
+        match self {
+            Error::Syntax(_) | Error::Resolver(_) => {
+                let diagnostics = self.diagnostics(source_map.source());
+                for diagnostic in &diagnostics {
+                    diagnostic.render(source_map, color, output)?;
+                }
+                let count = diagnostics.len();
+                writeln!(output, "{count} error{}", if count == 1 { "" } else { "s" })
+            }
+            Error::Runtime(error) => error.display_with(source_map, color, output),
+        }
+    }
+}
+
+/// A problem found while lexing or parsing
+#[derive(Debug)]
+pub enum SyntaxError {
+    Lexer(LexerError),
+    Parser(ParserError),
+}
+
+impl SyntaxError {
+    pub fn span(&self, source: &str) -> Span {
+        match self {
+            SyntaxError::Lexer(error) => error.span(source),
+            SyntaxError::Parser(error) => error.span(),
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            SyntaxError::Lexer(error) => error.message(),
+            SyntaxError::Parser(error) => error.message(),
+        }
+    }
+
+    pub fn diagnostic(&self, source: &str) -> Diagnostic {
+        match self {
+            SyntaxError::Lexer(error) => error.diagnostic(source),
+            SyntaxError::Parser(error) => error.diagnostic(),
+        }
+    }
+}
+
+/// Lex, parse and resolve some source, ready to be given to [`Interpreter::interpret`] or
+/// [`Vm::interpret`]
+pub fn parse(source: &str) -> Result<Vec<Declaration>, Error> {
+    let lexer_result = Lexer::lex(source);
+    // Whatever the lexer managed to make of the source is still parsed, so mistakes further on
+    // are reported at the same time
+    let parse_result = Parser::parse(&lexer_result.tokens);
+    if !lexer_result.errors.is_empty() || !parse_result.errors.is_empty() {
+        let lexer_spans = lexer_result
+            .errors
+            .iter()
+            .map(|error| error.span(source))
+            .collect::<Vec<_>>();
+        let mut errors = lexer_result
+            .errors
+            .into_iter()
+            .map(SyntaxError::Lexer)
+            .chain(
+                parse_result
+                    .errors
+                    .into_iter()
+                    .filter(|error| {
+                        !caused_by_lexer_error(error.span(), &lexer_spans, &lexer_result.tokens)
+                    })
+                    .map(SyntaxError::Parser),
+            )
+            .collect::<Vec<_>>();
+        errors.sort_by_key(|error| error.span(source).start);
+        errors.dedup_by(|a, b| a.span(source) == b.span(source) && a.message() == b.message());
+        return Err(Error::Syntax(errors));
+    }
+
+    let resolver_errors = Resolver::resolve(source, &parse_result.declarations);
+    if !resolver_errors.is_empty() {
+        return Err(Error::Resolver(resolver_errors));
+    }
+
+    Ok(parse_result.declarations)
+}
+
+/// Whether a parser error is only there because the lexer couldn't make a token out of some
+/// source, which would repeat the lexer's error. That's the case when the parser error comes
+/// after a lexer error in the same statement.
+fn caused_by_lexer_error(parser_error: Span, lexer_errors: &[Span], tokens: &[Token]) -> bool {
+    lexer_errors.iter().any(|lexer_error| {
+        lexer_error.start <= parser_error.start
+            && !tokens.iter().any(|token| {
+                matches!(token.type_, TokenType::Semicolon | TokenType::RightBrace)
+                    && token.span.start >= lexer_error.start
+                    && token.span.end <= parser_error.start
+            })
+    })
+}
+
+/// Run some source with a fresh [`Interpreter`]
+pub fn run(source: &str) -> Result<(), Error> {
+    Interpreter::new().run(source)
+}
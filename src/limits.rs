@@ -0,0 +1,117 @@
+//! Limits on the resources a script can use, for running code that can't be trusted. Both
+//! backends enforce the same limits.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{interpreter::error::Error, span::Span};
+
+/// How many steps to take between looking at the clock, which is slow compared to a step
+const STEPS_BETWEEN_CLOCK_CHECKS: u64 = 1024;
+
+/// Limits on what each run of a script can do, set with
+/// [`Interpreter::set_limits`](crate::Interpreter::set_limits) or
+/// [`Vm::set_limits`](crate::vm::Vm::set_limits). Nothing is limited by default.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    /// The most steps a run can take. The tree walking interpreter takes a step for every
+    /// expression it evaluates, and the virtual machine for every instruction it executes.
+    pub max_steps: Option<u64>,
+    /// How long a run can take. The clock is only checked every so many steps, so runs can go a
+    /// little over.
+    pub timeout: Option<Duration>,
+    /// The most elements a list or map, or bytes a string, can hold
+    pub max_collection_size: Option<usize>,
+    /// Stops the script at its next step once cancelled
+    pub cancellation: Option<CancellationHandle>,
+}
+
+/// Lets another thread stop a running script. Clones share the same cancellation.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationHandle(Arc<AtomicBool>);
+
+impl CancellationHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Keeps track of how much of its limits the current run has used
+#[derive(Debug, Default)]
+pub(crate) struct Budget {
+    limits: Limits,
+    steps: u64,
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    pub(crate) fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    /// Start a run with nothing used yet
+    pub(crate) fn start(&mut self) {
+        self.steps = 0;
+        self.deadline = self.limits.timeout.map(|timeout| Instant::now() + timeout);
+    }
+
+    /// Take a step, failing if that goes over a limit. `span` is only called to report an error.
+    pub(crate) fn step(&mut self, span: impl FnOnce() -> Span) -> Result<(), Error> {
+        self.steps += 1;
+        if let Some(limit) = self.limits.max_steps
+            && self.steps > limit
+        {
+            return Err(Error::StepLimitExceeded {
+                limit,
+                span: span(),
+            });
+        }
+        if let Some(cancellation) = &self.limits.cancellation
+            && cancellation.is_cancelled()
+        {
+            return Err(Error::Cancelled(span()));
+        }
+        if self.steps.is_multiple_of(STEPS_BETWEEN_CLOCK_CHECKS)
+            && let (Some(deadline), Some(timeout)) = (self.deadline, self.limits.timeout)
+            && Instant::now() >= deadline
+        {
+            return Err(Error::TimedOut {
+                timeout,
+                span: span(),
+            });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn max_collection_size(&self) -> Option<usize> {
+        self.limits.max_collection_size
+    }
+
+    /// Fail if a collection or string of `size` is bigger than allowed
+    pub(crate) fn check_size(&self, size: usize, span: Span) -> Result<(), Error> {
+        check_size(self.limits.max_collection_size, size, span)
+    }
+}
+
+pub(crate) fn check_size(limit: Option<usize>, size: usize, span: Span) -> Result<(), Error> {
+    match limit {
+        Some(limit) if size > limit => Err(Error::CollectionTooLarge { limit, span }),
+        _ => Ok(()),
+    }
+}
@@ -1,71 +1,182 @@
-#![feature(let_chains)]
-#![feature(try_trait_v2)]
-
 mod error;
-mod expression;
-mod interpreter;
-mod lexer;
-mod parser;
+mod golden;
 mod repl;
-mod span;
-mod statement;
 
-use std::fs;
+use std::{fs, io, path::Path, process::ExitCode};
 
 use error::Error;
+use rusty_lox::{disassemble, with_stack, Color, Interpreter, SourceMap, Vm};
+
+#[derive(PartialEq)]
+enum Backend {
+    TreeWalker,
+    Vm,
+    /// Print the bytecode the script compiles to instead of running it
+    DumpBytecode,
+}
 
-use crate::{interpreter::Interpreter, lexer::Lexer, parser::Parser};
+fn main() -> ExitCode {
+    // Give Lox code room to recurse as deep as the interpreter allows
+    match with_stack(run) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            if let Error::Io(io_error) = &error {
+                eprintln!("Error: {io_error}");
+            };
+            error.exit_code()
+        }
+    }
+}
 
-fn main() -> Result<(), Error> {
-    let argc = std::env::args().count();
+fn run() -> Result<(), Error> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
 
-    if argc > 2 {
-        print_usage();
-        return Err(Error::Usage);
+    let mut backend = Backend::TreeWalker;
+    if let Some(index) = args.iter().position(|arg| arg == "--vm") {
+        args.remove(index);
+        backend = Backend::Vm;
+    };
+    if let Some(index) = args.iter().position(|arg| arg == "--dump-bytecode") {
+        args.remove(index);
+        backend = Backend::DumpBytecode;
+    };
+    // Diagnostics are written to stdout
+    let mut color = Color::auto(&io::stdout());
+    if let Some(index) = args.iter().position(|arg| arg.starts_with("--color=")) {
+        color = match &args.remove(index)["--color=".len()..] {
+            "auto" => Color::auto(&io::stdout()),
+            "always" => Color::Always,
+            "never" => Color::Never,
+            _ => {
+                print_usage();
+                return Err(Error::Usage);
+            }
+        };
+    };
+    let mut diagnostics = Diagnostics::Text;
+    if let Some(index) = args
+        .iter()
+        .position(|arg| arg.starts_with("--diagnostics="))
+    {
+        diagnostics = match &args.remove(index)["--diagnostics=".len()..] {
+            "text" => Diagnostics::Text,
+            "json" => Diagnostics::Json,
+            _ => {
+                print_usage();
+                return Err(Error::Usage);
+            }
+        };
     };
 
-    if argc == 1 {
-        return repl::run_repl();
+    if args.first().is_some_and(|arg| arg == "test") {
+        let (Some(directory), 2) = (args.get(1), args.len()) else {
+            print_usage();
+            return Err(Error::Usage);
+        };
+        let backend = match backend {
+            Backend::TreeWalker => golden::Backend::TreeWalker,
+            Backend::Vm => golden::Backend::Vm,
+            Backend::DumpBytecode => {
+                print_usage();
+                return Err(Error::Usage);
+            }
+        };
+        return run_tests(Path::new(directory), backend);
     }
 
-    let file_contents = load_file_from_args()?;
+    if args.len() > 1 {
+        print_usage();
+        return Err(Error::Usage);
+    };
 
-    let lexer_result = Lexer::lex(&file_contents);
+    let Some(file_path) = args.first() else {
+        // The REPL only supports the tree walking interpreter
+        if backend != Backend::TreeWalker {
+            print_usage();
+            return Err(Error::Usage);
+        }
+        return repl::run_repl(color);
+    };
 
-    if !lexer_result.errors.is_empty() {
-        println!("Got lexing errors");
-        lexer_result
-            .errors
-            .iter()
-            .for_each(|e| e.display(&file_contents));
-        return Ok(());
-    }
+    let file_contents = fs::read_to_string(file_path)?;
 
-    let parse_result = Parser::parse(&lexer_result.tokens);
+    let result = rusty_lox::parse(&file_contents).and_then(|declarations| {
+        match backend {
+            Backend::TreeWalker => Interpreter::new().interpret(&file_contents, declarations),
+            Backend::Vm => Vm::new().interpret(&file_contents, &declarations),
+            Backend::DumpBytecode => {
+                print!("{}", disassemble(&file_contents, &declarations));
+                Ok(())
+            }
+        }
+        .map_err(rusty_lox::Error::Runtime)
+    });
 
-    if !parse_result.errors.is_empty() {
-        parse_result
-            .errors
-            .iter()
-            .for_each(|e| e.display(&file_contents));
-        return Ok(());
+    if let Err(error) = result {
+        if let Some(code) = error.exit_code() {
+            return exit(code);
+        };
+
+        let source_map = SourceMap::with_file_name(file_path, &file_contents);
+        match diagnostics {
+            Diagnostics::Text => error.display_with(&source_map, color, &mut io::stdout())?,
+            Diagnostics::Json => {
+                for diagnostic in error.diagnostics(&file_contents) {
+                    eprintln!("{}", diagnostic.to_json(&source_map));
+                }
+            }
+        }
+        return Err(match error {
+            rusty_lox::Error::Syntax(_) | rusty_lox::Error::Resolver(_) => Error::Compile,
+            rusty_lox::Error::Runtime(_) => Error::Runtime,
+        });
     }
 
-    let result = Interpreter::new().interpret(&file_contents, parse_result.declarations);
+    Ok(())
+}
 
-    if let Err(error) = result {
-        error.display(&file_contents);
+/// Exit the way a script asked to with `exit`
+fn exit(code: u8) -> Result<(), Error> {
+    match code {
+        0 => Ok(()),
+        code => Err(Error::Exit(code)),
     }
+}
 
-    Ok(())
+/// How errors in a script are reported
+enum Diagnostics {
+    /// Rendered for people to read, on stdout
+    Text,
+    /// One JSON object per line on stderr, for tools to read
+    Json,
 }
 
-fn load_file_from_args() -> Result<String, Error> {
-    let file_path = std::env::args().nth(1).ok_or(Error::Usage)?;
+/// Run every script in a directory, checking its output against its `// expect:` comments
+fn run_tests(directory: &Path, backend: golden::Backend) -> Result<(), Error> {
+    let scripts = golden::find_scripts(directory)?;
+    let mut failed = 0;
+    for script in &scripts {
+        let source = fs::read_to_string(script)?;
+        if let Err(failures) = golden::check(&source, backend) {
+            failed += 1;
+            println!("FAIL {}", script.display());
+            for failure in failures {
+                println!("     {failure}");
+            }
+        }
+    }
 
-    Ok(fs::read_to_string(file_path)?)
+    println!("{} passed, {} failed", scripts.len() - failed, failed);
+    if failed > 0 {
+        return Err(Error::TestsFailed);
+    }
+    Ok(())
 }
 
 fn print_usage() {
-    println!("Usage: rusty-lox [file]");
+    println!(
+        "Usage: rusty-lox [--vm | --dump-bytecode] [--diagnostics=text|json] \
+         [--color=auto|always|never] [file]"
+    );
+    println!("       rusty-lox [--vm] test <directory>");
 }
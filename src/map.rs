@@ -0,0 +1,139 @@
+//! The storage behind Lox maps, shared by both backends
+
+use std::{collections::HashMap, fmt};
+
+use crate::interpreter::value::fmt_quoted;
+
+/// A Lox value that can be used as a map key. Only values compared by contents can be keys, so
+/// callables, instances and collections can't.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    String(String),
+    /// The bits of the number, with `-0` normalised to `0` so they compare equal
+    Number(u64),
+    Boolean(bool),
+    Nil,
+}
+
+impl MapKey {
+    pub fn number(number: f64) -> Self {
+        let number = if number == 0.0 { 0.0 } else { number };
+        MapKey::Number(number.to_bits())
+    }
+}
+
+/// Quotes strings with the alternate flag (`{:#}`), like values do
+impl fmt::Display for MapKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapKey::String(string) if f.alternate() => fmt_quoted(string, f),
+            MapKey::String(string) => write!(f, "{string}"),
+            MapKey::Number(bits) => write!(f, "{}", f64::from_bits(*bits)),
+            MapKey::Boolean(boolean) => write!(f, "{boolean}"),
+            MapKey::Nil => write!(f, "nil"),
+        }
+    }
+}
+
+/// A hash map that remembers the order keys were first inserted in, so iterating over and
+/// printing maps is deterministic
+#[derive(Debug, Clone)]
+pub struct Map<V> {
+    entries: Vec<(MapKey, V)>,
+    indices: HashMap<MapKey, usize>,
+}
+
+impl<V> Default for Map<V> {
+    fn default() -> Self {
+        Self {
+            entries: vec![],
+            indices: HashMap::new(),
+        }
+    }
+}
+
+impl<V> Map<V> {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &MapKey) -> Option<&V> {
+        self.indices.get(key).map(|index| &self.entries[*index].1)
+    }
+
+    pub fn contains_key(&self, key: &MapKey) -> bool {
+        self.indices.contains_key(key)
+    }
+
+    pub fn insert(&mut self, key: MapKey, value: V) {
+        match self.indices.get(&key) {
+            Some(index) => self.entries[*index].1 = value,
+            None => {
+                self.indices.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &MapKey) -> Option<V> {
+        let index = self.indices.remove(key)?;
+        let (_, value) = self.entries.remove(index);
+        for moved_index in self.indices.values_mut() {
+            if *moved_index > index {
+                *moved_index -= 1;
+            }
+        }
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&MapKey, &V)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &MapKey> {
+        self.entries.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+}
+
+impl<V: fmt::Display> fmt::Display for Map<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{")?;
+        for (index, (key, value)) in self.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            // Strings are quoted so they can't be confused with other types of key or value
+            write!(f, "{key:#}: {value:#}")?;
+        }
+        write!(f, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_insertion_order() {
+        let mut map = Map::default();
+        map.insert(MapKey::String("b".into()), 1);
+        map.insert(MapKey::Nil, 2);
+        map.insert(MapKey::number(-0.0), 3);
+        map.insert(MapKey::String("b".into()), 4);
+        assert_eq!(map.to_string(), "{\"b\": 4, nil: 2, 0: 3}");
+        assert_eq!(map.get(&MapKey::number(0.0)), Some(&3));
+
+        assert_eq!(map.remove(&MapKey::Nil), Some(2));
+        assert_eq!(map.remove(&MapKey::Nil), None);
+        assert_eq!(map.get(&MapKey::number(0.0)), Some(&3));
+        assert_eq!(map.keys().cloned().collect::<Vec<_>>().len(), 2);
+    }
+}
@@ -1,15 +1,21 @@
-use std::rc::Rc;
+use std::{
+    io::{self, Write},
+    rc::Rc,
+};
 
 use crate::{
+    diagnostic::{Color, Diagnostic},
     expression::{
         binary_expression, boolean_literal_expression, grouping_expression, nil_literal,
         number_literal_expression, string_literal_expression, unary_expression,
-        AssignmentExpression, CallExpression, Expression, LiteralExpression, LogicalExpression,
-        VariableExpression,
+        AssignmentExpression, CallExpression, Expression, FunctionExpression, GetExpression,
+        IndexExpression, InterpolationExpression, ListExpression, LogicalExpression, MapExpression,
+        SetExpression, SetIndexExpression, SuperExpression, ThisExpression, VariableExpression,
     },
-    lexer::{self, Token, TokenType},
+    lexer::{Token, TokenType},
+    source_map::SourceMap,
     span::Span,
-    statement::{Declaration, Statement},
+    statement::{CatchClause, Declaration, FunctionDeclaration, Statement},
 };
 
 pub struct Parser {
@@ -45,8 +51,17 @@ impl Parser {
     }
 
     fn parse_declaration(&mut self, tokens: &[Token]) -> Option<Declaration> {
-        if self.consume_token_if_in_vec(tokens, &vec![TokenType::Fun]) {
-            return self.parse_function_declaration(tokens);
+        if self.consume_token_if_in_vec(tokens, &vec![TokenType::Class]) {
+            return self.parse_class_declaration(tokens);
+        };
+        // `fun` without a name starts an anonymous function expression
+        if self.token_is(tokens, 0, TokenType::Fun)
+            && self.token_is(tokens, 1, TokenType::Identifier)
+        {
+            self.current_index += 1;
+            return Some(Declaration::Function(
+                self.parse_function_declaration(tokens)?,
+            ));
         };
         if self.consume_token_if_in_vec(tokens, &vec![TokenType::Var]) {
             return self.parse_variable_declaration(tokens);
@@ -55,17 +70,60 @@ impl Parser {
         Some(Declaration::Statement(self.parse_statement(tokens)?))
     }
 
-    fn parse_function_declaration(&mut self, tokens: &[Token]) -> Option<Declaration> {
+    fn parse_class_declaration(&mut self, tokens: &[Token]) -> Option<Declaration> {
+        let name = self.consume_token_of_type(tokens, TokenType::Identifier)?;
+
+        let superclass = if self.consume_token_if_in_vec(tokens, &vec![TokenType::Less]) {
+            Some(Rc::new(Expression::Variable(VariableExpression::new(
+                self.consume_token_of_type(tokens, TokenType::Identifier)?,
+            ))))
+        } else {
+            None
+        };
+
+        self.consume_token_of_type(tokens, TokenType::LeftBrace)?;
+
+        let mut methods = Vec::new();
+        while self.current_token(tokens).map(|t| t.type_) != Some(TokenType::RightBrace)
+            && self.current_token(tokens).map(|t| t.type_) != Some(TokenType::Eof)
+            && self.current_token(tokens).is_some()
+        {
+            methods.push(self.parse_function_declaration(tokens)?);
+        }
+        self.consume_token_of_type(tokens, TokenType::RightBrace)?;
+
+        Some(Declaration::Class {
+            name,
+            superclass,
+            methods,
+        })
+    }
+
+    /// Parse a function's name, parameters and body. Used for both `fun` declarations (after
+    /// the `fun` keyword has been consumed) and class methods.
+    fn parse_function_declaration(&mut self, tokens: &[Token]) -> Option<FunctionDeclaration> {
         let name = self.consume_token_of_type(tokens, TokenType::Identifier)?;
+        let parameters = self.parse_parameters(tokens, name.span)?;
+        self.consume_token_of_type(tokens, TokenType::LeftBrace)?;
+        let body = self.parse_block(tokens)?;
+
+        Some(FunctionDeclaration {
+            name,
+            parameters,
+            body,
+        })
+    }
+
+    /// Parse a parenthesised list of parameter names. `callee_span` is where to report having
+    /// too many of them.
+    fn parse_parameters(&mut self, tokens: &[Token], callee_span: Span) -> Option<Vec<Token>> {
         self.consume_token_of_type(tokens, TokenType::LeftParen)?;
         let mut parameters = Vec::new();
         if self.current_token(tokens)?.type_ != TokenType::RightParen {
             loop {
                 // Make sure there's not too many arguments
                 if parameters.len() >= 255 {
-                    self.errors.push(Error::TwoManyArguments {
-                        callee_span: name.span,
-                    });
+                    self.errors.push(Error::TwoManyArguments { callee_span });
                     return None;
                 };
 
@@ -81,15 +139,75 @@ impl Parser {
             }
         }
         self.consume_token_of_type(tokens, TokenType::RightParen)?;
+        Some(parameters)
+    }
 
+    /// Parse an anonymous function's parameters and body, after the `fun` keyword
+    fn parse_function_expression(&mut self, tokens: &[Token]) -> Option<Rc<Expression>> {
+        let keyword = tokens.get(self.current_index - 1).cloned()?;
+        let parameters = self.parse_parameters(tokens, keyword.span)?;
         self.consume_token_of_type(tokens, TokenType::LeftBrace)?;
         let body = self.parse_block(tokens)?;
 
-        Some(Declaration::Function {
-            name,
+        Some(Rc::new(Expression::Function(FunctionExpression {
+            keyword,
             parameters,
             body,
-        })
+        })))
+    }
+
+    /// Parse an arrow function, `(a, b) => a + b` or `(a, b) => { return a + b; }`, starting at
+    /// its opening parenthesis
+    fn parse_arrow_function(&mut self, tokens: &[Token]) -> Option<Rc<Expression>> {
+        let opening_paren_span = self.current_token(tokens)?.span;
+        let parameters = self.parse_parameters(tokens, opening_paren_span)?;
+        let keyword = self.consume_token_of_type(tokens, TokenType::Arrow)?;
+        // A brace starts a block body, so returning a map literal needs it in parentheses
+        let body = if self.consume_token_if_in_vec(tokens, &vec![TokenType::LeftBrace]) {
+            self.parse_block(tokens)?
+        } else {
+            let value = self.parse_expression(tokens)?;
+            Rc::new(vec![Declaration::Statement(Statement::Return {
+                keyword: keyword.clone(),
+                value: Some(value),
+            })])
+        };
+
+        Some(Rc::new(Expression::Function(FunctionExpression {
+            keyword,
+            parameters,
+            body,
+        })))
+    }
+
+    /// Whether the tokens from the current one on are an arrow function's parameter list
+    /// followed by `=>`, rather than a grouping expression
+    fn is_arrow_function(&self, tokens: &[Token]) -> bool {
+        if !self.token_is(tokens, 0, TokenType::LeftParen) {
+            return false;
+        }
+        let mut offset = 1;
+        if !self.token_is(tokens, offset, TokenType::RightParen) {
+            loop {
+                if !self.token_is(tokens, offset, TokenType::Identifier) {
+                    return false;
+                }
+                offset += 1;
+                if !self.token_is(tokens, offset, TokenType::Comma) {
+                    break;
+                }
+                offset += 1;
+            }
+        }
+        self.token_is(tokens, offset, TokenType::RightParen)
+            && self.token_is(tokens, offset + 1, TokenType::Arrow)
+    }
+
+    /// Whether the token `offset` tokens after the current one has the given type
+    fn token_is(&self, tokens: &[Token], offset: usize, token_type: TokenType) -> bool {
+        tokens
+            .get(self.current_index + offset)
+            .is_some_and(|token| token.type_ == token_type)
     }
 
     fn parse_variable_declaration(&mut self, tokens: &[Token]) -> Option<Declaration> {
@@ -124,6 +242,20 @@ impl Parser {
         if self.consume_token_if_in_vec(tokens, &vec![TokenType::Return]) {
             return self.parse_return_statement(tokens);
         }
+        // Break and continue statements
+        if self.consume_token_if_in_vec(tokens, &vec![TokenType::Break, TokenType::Continue]) {
+            return self.parse_loop_jump_statement(tokens);
+        }
+        // Exception handling
+        if self.consume_token_if_in_vec(tokens, &vec![TokenType::Try]) {
+            return self.parse_try_statement(tokens);
+        }
+        if self.consume_token_if_in_vec(tokens, &vec![TokenType::Throw]) {
+            let keyword = tokens.get(self.current_index - 1).cloned()?;
+            let value = self.parse_expression(tokens)?;
+            self.consume_token_of_type(tokens, TokenType::Semicolon)?;
+            return Some(Statement::Throw { keyword, value });
+        }
         // Block statement
         if self.consume_token_if_in_vec(tokens, &vec![TokenType::LeftBrace]) {
             return Some(Statement::Block(self.parse_block(tokens)?));
@@ -136,26 +268,73 @@ impl Parser {
     fn parse_return_statement(&mut self, tokens: &[Token]) -> Option<Statement> {
         let keyword = tokens.get(self.current_index - 1).cloned()?;
 
-        let current_token = self.current_token(tokens)?;
-
-        let expression = if current_token.type_ == TokenType::Semicolon {
-            Rc::new(Expression::Literal(LiteralExpression::Nil(
-                current_token.span,
-            )))
+        let value = if self.current_token(tokens)?.type_ == TokenType::Semicolon {
+            None
         } else {
-            self.parse_expression(tokens)?
+            Some(self.parse_expression(tokens)?)
         };
 
         self.consume_token_of_type(tokens, TokenType::Semicolon)?;
 
-        Some(Statement::Return {
+        Some(Statement::Return { keyword, value })
+    }
+
+    fn parse_try_statement(&mut self, tokens: &[Token]) -> Option<Statement> {
+        let keyword = tokens.get(self.current_index - 1).cloned()?;
+        self.consume_token_of_type(tokens, TokenType::LeftBrace)?;
+        let body = self.parse_block(tokens)?;
+
+        let catch = if self.consume_token_if_in_vec(tokens, &vec![TokenType::Catch]) {
+            self.consume_token_of_type(tokens, TokenType::LeftParen)?;
+            let name = self.consume_token_of_type(tokens, TokenType::Identifier)?;
+            self.consume_token_of_type(tokens, TokenType::RightParen)?;
+            self.consume_token_of_type(tokens, TokenType::LeftBrace)?;
+            let body = self.parse_block(tokens)?;
+            Some(CatchClause { name, body })
+        } else {
+            None
+        };
+
+        let finally = if self.consume_token_if_in_vec(tokens, &vec![TokenType::Finally]) {
+            self.consume_token_of_type(tokens, TokenType::LeftBrace)?;
+            Some(self.parse_block(tokens)?)
+        } else {
+            None
+        };
+
+        if catch.is_none() && finally.is_none() {
+            self.errors.push(Error::TryWithoutCatchOrFinally {
+                try_span: keyword.span,
+            });
+            return None;
+        }
+
+        Some(Statement::Try {
             keyword,
-            value: expression,
+            body,
+            catch,
+            finally,
         })
     }
 
+    fn parse_loop_jump_statement(&mut self, tokens: &[Token]) -> Option<Statement> {
+        let keyword = tokens.get(self.current_index - 1).cloned()?;
+        self.consume_token_of_type(tokens, TokenType::Semicolon)?;
+
+        if keyword.type_ == TokenType::Break {
+            Some(Statement::Break(keyword))
+        } else {
+            Some(Statement::Continue(keyword))
+        }
+    }
+
     fn parse_for_statement(&mut self, tokens: &[Token]) -> Option<Statement> {
         self.consume_token_of_type(tokens, TokenType::LeftParen)?;
+
+        if self.token_is(tokens, 0, TokenType::Var) && self.token_is(tokens, 2, TokenType::In) {
+            return self.parse_for_in_statement(tokens);
+        }
+
         let initialiser = if self.consume_token_if_in_vec(tokens, &vec![TokenType::Semicolon]) {
             None
         } else if self.consume_token_if_in_vec(tokens, &vec![TokenType::Var]) {
@@ -168,9 +347,10 @@ impl Parser {
 
         let current_token = self.current_token(tokens)?;
         let condition = if current_token.type_ != TokenType::Semicolon {
-            self.parse_expression(tokens)
+            self.parse_expression(tokens)?
         } else {
-            None
+            // A missing condition loops forever
+            boolean_literal_expression(current_token.span, true)
         };
 
         self.consume_token_of_type(tokens, TokenType::Semicolon);
@@ -185,21 +365,13 @@ impl Parser {
         self.consume_token_of_type(tokens, TokenType::RightParen);
 
         let body = {
-            let mut body = self.parse_statement(tokens)?;
-
-            if let Some(increment) = increment {
-                body = Statement::Block(Rc::new(vec![
-                    Declaration::Statement(body),
-                    Declaration::Statement(Statement::Expression(increment)),
-                ]));
-            }
-
-            if let Some(condition) = condition {
-                body = Statement::While {
-                    condition,
-                    body: Box::new(body),
-                };
-            }
+            // The increment is kept separate from the body, rather than appended to it, so
+            // that `continue` still runs it
+            let mut body = Statement::While {
+                condition,
+                body: Box::new(self.parse_statement(tokens)?),
+                increment,
+            };
 
             if let Some(initialiser) = initialiser {
                 body = Statement::Block(Rc::new(vec![initialiser, Declaration::Statement(body)]));
@@ -210,6 +382,22 @@ impl Parser {
         Some(body)
     }
 
+    /// Parse the rest of a `for (var name in iterable)` loop, after the opening paren
+    fn parse_for_in_statement(&mut self, tokens: &[Token]) -> Option<Statement> {
+        self.consume_token_of_type(tokens, TokenType::Var)?;
+        let variable = self.consume_token_of_type(tokens, TokenType::Identifier)?;
+        self.consume_token_of_type(tokens, TokenType::In)?;
+        let iterable = self.parse_expression(tokens)?;
+        self.consume_token_of_type(tokens, TokenType::RightParen)?;
+        let body = Box::new(self.parse_statement(tokens)?);
+
+        Some(Statement::ForIn {
+            variable,
+            iterable,
+            body,
+        })
+    }
+
     fn parse_while_statement(&mut self, tokens: &[Token]) -> Option<Statement> {
         self.consume_token_of_type(tokens, TokenType::LeftParen)?;
         let condition = self.parse_expression(tokens)?;
@@ -217,7 +405,11 @@ impl Parser {
 
         let body = Box::new(self.parse_statement(tokens)?);
 
-        Some(Statement::While { condition, body })
+        Some(Statement::While {
+            condition,
+            body,
+            increment: None,
+        })
     }
 
     fn parse_if_statement(&mut self, tokens: &[Token]) -> Option<Statement> {
@@ -274,8 +466,25 @@ impl Parser {
             let value = self.parse_assignment(tokens)?;
 
             if let Expression::Variable(variable_expression) = &*expression {
-                return Some(Rc::new(Expression::Assignment(AssignmentExpression {
-                    name: variable_expression.name.clone(),
+                return Some(Rc::new(Expression::Assignment(AssignmentExpression::new(
+                    variable_expression.name.clone(),
+                    value,
+                ))));
+            };
+
+            if let Expression::Get(get_expression) = &*expression {
+                return Some(Rc::new(Expression::Set(SetExpression {
+                    object: get_expression.object.clone(),
+                    name: get_expression.name.clone(),
+                    value,
+                })));
+            };
+
+            if let Expression::Index(index_expression) = &*expression {
+                return Some(Rc::new(Expression::SetIndex(SetIndexExpression {
+                    object: index_expression.object.clone(),
+                    index: index_expression.index.clone(),
+                    closing_bracket: index_expression.closing_bracket.clone(),
                     value,
                 })));
             };
@@ -394,6 +603,21 @@ impl Parser {
         loop {
             if self.consume_token_if_in_vec(tokens, &vec![TokenType::LeftParen]) {
                 expression = self.parse_call_arguments(tokens, expression)?;
+            } else if self.consume_token_if_in_vec(tokens, &vec![TokenType::Dot]) {
+                let name = self.consume_token_of_type(tokens, TokenType::Identifier)?;
+                expression = Rc::new(Expression::Get(GetExpression {
+                    object: expression,
+                    name,
+                }));
+            } else if self.consume_token_if_in_vec(tokens, &vec![TokenType::LeftBracket]) {
+                let index = self.parse_expression(tokens)?;
+                let closing_bracket =
+                    self.consume_token_of_type(tokens, TokenType::RightBracket)?;
+                expression = Rc::new(Expression::Index(IndexExpression {
+                    object: expression,
+                    index,
+                    closing_bracket,
+                }));
             } else {
                 break;
             }
@@ -435,6 +659,77 @@ impl Parser {
         })))
     }
 
+    /// Parse the elements of a list literal, after the opening bracket. A trailing comma is
+    /// allowed
+    fn parse_list(&mut self, tokens: &[Token]) -> Option<Rc<Expression>> {
+        let opening_bracket = tokens.get(self.current_index - 1).cloned()?;
+        let mut elements = Vec::new();
+
+        while self.current_token(tokens)?.type_ != TokenType::RightBracket {
+            elements.push(self.parse_expression(tokens)?);
+            if !self.consume_token_if_in_vec(tokens, &vec![TokenType::Comma]) {
+                break;
+            }
+        }
+
+        let closing_bracket = self.consume_token_of_type(tokens, TokenType::RightBracket)?;
+
+        Some(Rc::new(Expression::List(ListExpression {
+            opening_bracket,
+            elements,
+            closing_bracket,
+        })))
+    }
+
+    /// Parse the rest of an interpolated string, after the text before its first `${`
+    fn parse_interpolation(&mut self, tokens: &[Token]) -> Option<Rc<Expression>> {
+        let mut parts = vec![string_literal_expression(
+            tokens.get(self.current_index - 1).cloned()?,
+        )];
+
+        loop {
+            parts.push(self.parse_expression(tokens)?);
+            self.consume_token_of_type(tokens, TokenType::RightBrace)?;
+            if self.consume_token_if_in_vec(tokens, &vec![TokenType::Interpolation]) {
+                parts.push(string_literal_expression(
+                    tokens.get(self.current_index - 1).cloned()?,
+                ));
+            } else {
+                let end = self.consume_token_of_type(tokens, TokenType::String_)?;
+                parts.push(string_literal_expression(end));
+                break;
+            }
+        }
+
+        Some(Rc::new(Expression::Interpolation(
+            InterpolationExpression { parts },
+        )))
+    }
+
+    /// Parse the entries of a map literal, after the opening brace. A trailing comma is allowed
+    fn parse_map(&mut self, tokens: &[Token]) -> Option<Rc<Expression>> {
+        let opening_brace = tokens.get(self.current_index - 1).cloned()?;
+        let mut entries = Vec::new();
+
+        while self.current_token(tokens)?.type_ != TokenType::RightBrace {
+            let key = self.parse_expression(tokens)?;
+            self.consume_token_of_type(tokens, TokenType::Colon)?;
+            let value = self.parse_expression(tokens)?;
+            entries.push((key, value));
+            if !self.consume_token_if_in_vec(tokens, &vec![TokenType::Comma]) {
+                break;
+            }
+        }
+
+        let closing_brace = self.consume_token_of_type(tokens, TokenType::RightBrace)?;
+
+        Some(Rc::new(Expression::Map(MapExpression {
+            opening_brace,
+            entries,
+            closing_brace,
+        })))
+    }
+
     fn parse_primary(&mut self, tokens: &[Token]) -> Option<Rc<Expression>> {
         if self.consume_token_if_in_vec(tokens, &vec![TokenType::False]) {
             let span = tokens.get(self.current_index - 1).unwrap().span;
@@ -458,10 +753,38 @@ impl Parser {
                 tokens.get(self.current_index - 1).unwrap().clone(),
             ));
         };
+        if self.consume_token_if_in_vec(tokens, &vec![TokenType::Interpolation]) {
+            return self.parse_interpolation(tokens);
+        };
+        if self.consume_token_if_in_vec(tokens, &vec![TokenType::Fun]) {
+            return self.parse_function_expression(tokens);
+        };
+        if self.is_arrow_function(tokens) {
+            return self.parse_arrow_function(tokens);
+        };
+        if self.consume_token_if_in_vec(tokens, &vec![TokenType::Super]) {
+            let keyword = tokens.get(self.current_index - 1).unwrap().clone();
+            self.consume_token_of_type(tokens, TokenType::Dot)?;
+            let method = self.consume_token_of_type(tokens, TokenType::Identifier)?;
+            return Some(Rc::new(Expression::Super(SuperExpression::new(
+                keyword, method,
+            ))));
+        }
+        if self.consume_token_if_in_vec(tokens, &vec![TokenType::This]) {
+            return Some(Rc::new(Expression::This(ThisExpression::new(
+                tokens.get(self.current_index - 1).unwrap().clone(),
+            ))));
+        }
         if self.consume_token_if_in_vec(tokens, &vec![TokenType::Identifier]) {
-            return Some(Rc::new(Expression::Variable(VariableExpression {
-                name: tokens.get(self.current_index - 1).unwrap().clone(),
-            })));
+            return Some(Rc::new(Expression::Variable(VariableExpression::new(
+                tokens.get(self.current_index - 1).unwrap().clone(),
+            ))));
+        }
+        if self.consume_token_if_in_vec(tokens, &vec![TokenType::LeftBracket]) {
+            return self.parse_list(tokens);
+        }
+        if self.consume_token_if_in_vec(tokens, &vec![TokenType::LeftBrace]) {
+            return self.parse_map(tokens);
         }
         if self.consume_token_if_in_vec(tokens, &vec![TokenType::LeftParen]) {
             let expression = self.parse_expression(tokens)?;
@@ -491,15 +814,13 @@ impl Parser {
     fn consume_token_of_type(&mut self, tokens: &[Token], token_type: TokenType) -> Option<Token> {
         let current_token = self.current_token(tokens);
         if current_token.is_none() {
-            self.errors.push(Error::UnexpectedEof);
+            self.errors.push(Error::UnexpectedEof {
+                span: tokens.last().map_or(Span::new(0, 0), |token| token.span),
+            });
             return None;
         };
         let current_token = current_token.unwrap();
         if current_token.type_ != token_type {
-            println!(
-                "Tryed to consume token of type {:?}, but got {:?} instead",
-                token_type, current_token
-            );
             self.errors.push(Error::UnexpectedToken {
                 expected_token_type: Some(token_type),
                 unexpected_token_type: current_token.type_.clone(),
@@ -553,48 +874,85 @@ impl Parser {
     }
 }
 
+#[derive(Debug)]
 pub enum Error {
     UnexpectedToken {
         expected_token_type: Option<TokenType>,
         unexpected_token_type: TokenType,
         span: Span,
     },
-    UnexpectedEof,
+    UnexpectedEof {
+        span: Span,
+    },
     InvalidAssignmentTarget {
         target_span: Span,
     },
     TwoManyArguments {
         callee_span: Span,
     },
+    TryWithoutCatchOrFinally {
+        try_span: Span,
+    },
 }
 
 impl Error {
-    pub fn display(&self, source: &str) {
+    pub fn span(&self) -> Span {
+        match self {
+            Error::UnexpectedToken { span, .. }
+            | Error::UnexpectedEof { span }
+            | Error::InvalidAssignmentTarget { target_span: span }
+            | Error::TwoManyArguments { callee_span: span }
+            | Error::TryWithoutCatchOrFinally { try_span: span } => *span,
+        }
+    }
+
+    pub fn message(&self) -> String {
         match self {
             Error::UnexpectedToken {
-                expected_token_type,
                 unexpected_token_type,
-                span,
-            } => {
-                lexer::Error::display_error(
-                    source,
-                    span,
-                    &format!("Unexpected token {:?}", unexpected_token_type),
-                );
-                if let Some(expected_token_type) = expected_token_type {
-                    println!(
-                        "  Info: \x1b[34mExpected token of type: {:?}\x1b[0m",
-                        expected_token_type
-                    );
-                }
-            }
-            Error::UnexpectedEof => todo!(),
-            Error::InvalidAssignmentTarget { target_span } => {
-                lexer::Error::display_error(source, target_span, "Invalid assignment target")
-            }
-            Error::TwoManyArguments { callee_span } => {
-                lexer::Error::display_error(source, callee_span, "Too many arguments to call")
+                ..
+            } => format!("Unexpected token {:?}", unexpected_token_type),
+            Error::UnexpectedEof { .. } => "Unexpected end of file".into(),
+            Error::InvalidAssignmentTarget { .. } => "Invalid assignment target".into(),
+            Error::TwoManyArguments { .. } => "Too many arguments to call".into(),
+            Error::TryWithoutCatchOrFinally { .. } => {
+                "Expected 'catch' or 'finally' after try block".into()
             }
         }
     }
+
+    /// A stable identifier for the kind of error, for tools to match on
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::UnexpectedToken { .. } => "PARSE001",
+            Error::UnexpectedEof { .. } => "PARSE002",
+            Error::InvalidAssignmentTarget { .. } => "PARSE003",
+            Error::TwoManyArguments { .. } => "PARSE004",
+            Error::TryWithoutCatchOrFinally { .. } => "PARSE005",
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(self.code(), self.message(), self.span());
+        match self {
+            Error::UnexpectedToken {
+                expected_token_type: Some(expected_token_type),
+                ..
+            } => diagnostic.with_note(format!("Expected token of type: {:?}", expected_token_type)),
+            _ => diagnostic,
+        }
+    }
+
+    pub fn display(&self, source: &str, output: &mut dyn Write) -> io::Result<()> {
+        self.display_with(&SourceMap::new(source), Color::Never, output)
+    }
+
+    pub fn display_with(
+        &self,
+        source_map: &SourceMap,
+        color: Color,
+        output: &mut dyn Write,
+    ) -> io::Result<()> {
+        self.diagnostic().render(source_map, color, output)
+    }
 }
@@ -1,11 +1,10 @@
 use std::io::{self, stdout, Write};
 
+use rusty_lox::{Color, Interpreter, SourceMap};
+
 use crate::error::Error;
-use crate::interpreter::Interpreter;
-use crate::lexer::Lexer;
-use crate::parser::Parser;
 
-pub fn run_repl() -> Result<(), Error> {
+pub fn run_repl(color: Color) -> Result<(), Error> {
     let mut buffer = String::new();
     let mut interpreter = Interpreter::new();
     let stdin = io::stdin();
@@ -13,17 +12,12 @@ pub fn run_repl() -> Result<(), Error> {
         print!("> ");
         stdout().flush()?;
         stdin.read_line(&mut buffer)?;
-        let lexer_result = Lexer::lex(&buffer);
-        let parse_result = Parser::parse(&lexer_result.tokens);
-
-        if !parse_result.errors.is_empty() {
-            let error = &parse_result.errors[0];
-            error.display(&buffer);
-        }
-        let result = interpreter.interpret(&buffer, parse_result.declarations);
 
-        if let Err(error) = result {
-            error.display(&buffer);
+        if let Err(error) = interpreter.run(&buffer) {
+            if let Some(code) = error.exit_code() {
+                return crate::exit(code);
+            };
+            error.display_with(&SourceMap::new(&buffer), color, &mut stdout())?;
         }
 
         if buffer == *"\n" {
@@ -0,0 +1,522 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+use crate::{
+    diagnostic::{Color, Diagnostic},
+    expression::{
+        AssignmentExpression, BinaryExpression, CallExpression, Expression, FunctionExpression,
+        GetExpression, GroupingExpression, IndexExpression, InterpolationExpression,
+        ListExpression, LogicalExpression, MapExpression, SetExpression, SetIndexExpression,
+        SuperExpression, ThisExpression, UnaryExpression, VariableExpression,
+    },
+    lexer::Token,
+    source_map::SourceMap,
+    span::Span,
+    statement::{CatchClause, Declaration, Statement},
+};
+
+/// Walks the syntax tree after parsing, working out which scope every variable refers to and
+/// reporting errors that can be found without running the program.
+pub struct Resolver<'a> {
+    source: &'a str,
+    /// Stack of local scopes. The value for each name is whether the variable has finished
+    /// being initialised. Globals are not tracked.
+    scopes: Vec<HashMap<String, bool>>,
+    current_function: FunctionType,
+    current_class: ClassType,
+    /// How many loops the code being resolved is nested in, within the current function
+    loop_depth: usize,
+    errors: Vec<Error>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+    Method,
+    Initialiser,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn resolve(source: &'a str, declarations: &[Declaration]) -> Vec<Error> {
+        let mut resolver = Resolver {
+            source,
+            scopes: vec![],
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+            loop_depth: 0,
+            errors: vec![],
+        };
+
+        resolver.resolve_declarations(declarations);
+
+        resolver.errors
+    }
+
+    fn resolve_declarations(&mut self, declarations: &[Declaration]) {
+        declarations
+            .iter()
+            .for_each(|declaration| self.resolve_declaration(declaration));
+    }
+
+    fn resolve_declaration(&mut self, declaration: &Declaration) {
+        match declaration {
+            Declaration::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                let enclosing_class = self.current_class;
+                self.current_class = ClassType::Class;
+
+                self.declare(name);
+                self.define(name);
+
+                if let Some(superclass) = superclass {
+                    if let Expression::Variable(VariableExpression {
+                        name: superclass_name,
+                        ..
+                    }) = &**superclass
+                        && superclass_name.span.slice(self.source) == name.span.slice(self.source)
+                    {
+                        self.errors
+                            .push(Error::ClassInheritsFromItself(superclass_name.clone()));
+                    };
+                    self.current_class = ClassType::Subclass;
+                    self.resolve_expression(superclass);
+                    self.begin_scope();
+                    self.define_name("super");
+                }
+
+                self.begin_scope();
+                self.define_name("this");
+                for method in methods {
+                    let function_type = if method.name.span.slice(self.source) == "init" {
+                        FunctionType::Initialiser
+                    } else {
+                        FunctionType::Method
+                    };
+                    self.resolve_function(&method.parameters, &method.body, function_type);
+                }
+                self.end_scope();
+
+                if superclass.is_some() {
+                    self.end_scope();
+                }
+
+                self.current_class = enclosing_class;
+            }
+            Declaration::Function(function) => {
+                self.declare(&function.name);
+                self.define(&function.name);
+                self.resolve_function(&function.parameters, &function.body, FunctionType::Function);
+            }
+            Declaration::Variable { name, initialiser } => {
+                self.declare(name);
+                if let Some(initialiser) = initialiser {
+                    self.resolve_expression(initialiser);
+                }
+                self.define(name);
+            }
+            Declaration::Statement(statement) => self.resolve_statement(statement),
+        }
+    }
+
+    fn resolve_function(
+        &mut self,
+        parameters: &[Token],
+        body: &[Declaration],
+        function_type: FunctionType,
+    ) {
+        let enclosing_function = self.current_function;
+        self.current_function = function_type;
+        // A loop around a function declaration can't be broken out of from inside the function
+        let enclosing_loop_depth = self.loop_depth;
+        self.loop_depth = 0;
+
+        // Parameters and the body share a single scope, matching the environment the
+        // interpreter creates for a call
+        self.begin_scope();
+        for parameter in parameters {
+            self.declare(parameter);
+            self.define(parameter);
+        }
+        self.resolve_declarations(body);
+        self.end_scope();
+
+        self.current_function = enclosing_function;
+        self.loop_depth = enclosing_loop_depth;
+    }
+
+    fn resolve_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Print(expression) | Statement::Expression(expression) => {
+                self.resolve_expression(expression)
+            }
+            Statement::Block(declarations) => {
+                self.begin_scope();
+                self.resolve_declarations(declarations);
+                self.end_scope();
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.resolve_expression(condition);
+                self.resolve_statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_statement(else_branch);
+                }
+            }
+            Statement::While {
+                condition,
+                body,
+                increment,
+            } => {
+                self.resolve_expression(condition);
+                self.loop_depth += 1;
+                self.resolve_statement(body);
+                self.loop_depth -= 1;
+                if let Some(increment) = increment {
+                    self.resolve_expression(increment);
+                }
+            }
+            Statement::ForIn {
+                variable,
+                iterable,
+                body,
+            } => {
+                self.resolve_expression(iterable);
+                self.begin_scope();
+                self.declare(variable);
+                self.define(variable);
+                self.loop_depth += 1;
+                self.resolve_statement(body);
+                self.loop_depth -= 1;
+                self.end_scope();
+            }
+            Statement::Break(keyword) => {
+                if self.loop_depth == 0 {
+                    self.errors.push(Error::BreakOutsideLoop(keyword.clone()));
+                }
+            }
+            Statement::Continue(keyword) => {
+                if self.loop_depth == 0 {
+                    self.errors
+                        .push(Error::ContinueOutsideLoop(keyword.clone()));
+                }
+            }
+            Statement::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                self.begin_scope();
+                self.resolve_declarations(body);
+                self.end_scope();
+                // The caught value and the catch block's declarations share a scope
+                if let Some(CatchClause { name, body }) = catch {
+                    self.begin_scope();
+                    self.declare(name);
+                    self.define(name);
+                    self.resolve_declarations(body);
+                    self.end_scope();
+                }
+                if let Some(finally) = finally {
+                    self.begin_scope();
+                    self.resolve_declarations(finally);
+                    self.end_scope();
+                }
+            }
+            Statement::Throw { value, .. } => self.resolve_expression(value),
+            Statement::Return { keyword, value } => {
+                if self.current_function == FunctionType::None {
+                    self.errors.push(Error::ReturnFromTopLevel(keyword.clone()));
+                }
+                if let Some(value) = value {
+                    if self.current_function == FunctionType::Initialiser {
+                        self.errors
+                            .push(Error::ReturnValueFromInitialiser(keyword.clone()));
+                    }
+                    self.resolve_expression(value);
+                }
+            }
+        }
+    }
+
+    fn resolve_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Assignment(AssignmentExpression { name, value, depth }) => {
+                self.resolve_expression(value);
+                depth.set(self.resolve_local(name.span.slice(self.source)));
+            }
+            Expression::Binary(BinaryExpression { left, right, .. })
+            | Expression::Logical(LogicalExpression { left, right, .. }) => {
+                self.resolve_expression(left);
+                self.resolve_expression(right);
+            }
+            Expression::Call(CallExpression {
+                callee, arguments, ..
+            }) => {
+                self.resolve_expression(callee);
+                arguments
+                    .iter()
+                    .for_each(|argument| self.resolve_expression(argument));
+            }
+            Expression::Function(FunctionExpression {
+                parameters, body, ..
+            }) => self.resolve_function(parameters, body, FunctionType::Function),
+            Expression::Get(GetExpression { object, .. }) => self.resolve_expression(object),
+            Expression::Grouping(GroupingExpression { expression }) => {
+                self.resolve_expression(expression)
+            }
+            Expression::Index(IndexExpression { object, index, .. }) => {
+                self.resolve_expression(object);
+                self.resolve_expression(index);
+            }
+            Expression::Interpolation(InterpolationExpression { parts }) => {
+                parts.iter().for_each(|part| self.resolve_expression(part))
+            }
+            Expression::List(ListExpression { elements, .. }) => elements
+                .iter()
+                .for_each(|element| self.resolve_expression(element)),
+            Expression::Literal(_) => {}
+            Expression::Map(MapExpression { entries, .. }) => {
+                for (key, value) in entries {
+                    self.resolve_expression(key);
+                    self.resolve_expression(value);
+                }
+            }
+            Expression::Set(SetExpression { object, value, .. }) => {
+                self.resolve_expression(value);
+                self.resolve_expression(object);
+            }
+            Expression::SetIndex(SetIndexExpression {
+                object,
+                index,
+                value,
+                ..
+            }) => {
+                self.resolve_expression(object);
+                self.resolve_expression(index);
+                self.resolve_expression(value);
+            }
+            Expression::Super(SuperExpression { keyword, depth, .. }) => {
+                match self.current_class {
+                    ClassType::None => self.errors.push(Error::SuperOutsideClass(keyword.clone())),
+                    ClassType::Class => self
+                        .errors
+                        .push(Error::SuperWithoutSuperclass(keyword.clone())),
+                    ClassType::Subclass => {}
+                }
+                depth.set(self.resolve_local("super"));
+            }
+            Expression::This(ThisExpression { keyword, depth }) => {
+                if self.current_class == ClassType::None {
+                    self.errors.push(Error::ThisOutsideClass(keyword.clone()));
+                    return;
+                }
+                depth.set(self.resolve_local("this"));
+            }
+            Expression::Unary(UnaryExpression { right, .. }) => self.resolve_expression(right),
+            Expression::Variable(VariableExpression { name, depth }) => {
+                let name_string = name.span.slice(self.source);
+                if self.scopes.last().and_then(|scope| scope.get(name_string)) == Some(&false) {
+                    self.errors
+                        .push(Error::ReadLocalInOwnInitialiser(name.clone()));
+                }
+                depth.set(self.resolve_local(name_string));
+            }
+        }
+    }
+
+    /// Find how many scopes up the given name was declared. `None` means it wasn't found, so
+    /// is assumed to be a global
+    fn resolve_local(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .position(|scope| scope.contains_key(name))
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &Token) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+        let name_string = name.span.slice(self.source);
+        if scope.contains_key(name_string) {
+            self.errors.push(Error::AlreadyDeclared(name.clone()));
+        }
+        scope.insert(name_string.to_string(), false);
+    }
+
+    fn define(&mut self, name: &Token) {
+        self.define_name(name.span.slice(self.source));
+    }
+
+    fn define_name(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), true);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    ReadLocalInOwnInitialiser(Token),
+    AlreadyDeclared(Token),
+    ReturnFromTopLevel(Token),
+    ThisOutsideClass(Token),
+    SuperOutsideClass(Token),
+    SuperWithoutSuperclass(Token),
+    BreakOutsideLoop(Token),
+    ContinueOutsideLoop(Token),
+    ClassInheritsFromItself(Token),
+    ReturnValueFromInitialiser(Token),
+}
+
+impl Error {
+    pub fn span(&self) -> Span {
+        match self {
+            Error::ReadLocalInOwnInitialiser(token)
+            | Error::AlreadyDeclared(token)
+            | Error::ReturnFromTopLevel(token)
+            | Error::ThisOutsideClass(token)
+            | Error::SuperOutsideClass(token)
+            | Error::SuperWithoutSuperclass(token)
+            | Error::BreakOutsideLoop(token)
+            | Error::ContinueOutsideLoop(token)
+            | Error::ClassInheritsFromItself(token)
+            | Error::ReturnValueFromInitialiser(token) => token.span,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Error::ReadLocalInOwnInitialiser(_) => {
+                "Can't read local variable in its own initialiser"
+            }
+            Error::AlreadyDeclared(_) => "Already a variable with this name in this scope",
+            Error::ReturnFromTopLevel(_) => "Can't return from top-level code",
+            Error::ThisOutsideClass(_) => "Can't use 'this' outside of a class",
+            Error::SuperOutsideClass(_) => "Can't use 'super' outside of a class",
+            Error::SuperWithoutSuperclass(_) => "Can't use 'super' in a class with no superclass",
+            Error::BreakOutsideLoop(_) => "Can't use 'break' outside of a loop",
+            Error::ContinueOutsideLoop(_) => "Can't use 'continue' outside of a loop",
+            Error::ClassInheritsFromItself(_) => "A class can't inherit from itself",
+            Error::ReturnValueFromInitialiser(_) => "Can't return a value from an initialiser",
+        }
+        .into()
+    }
+
+    /// A stable identifier for the kind of error, for tools to match on
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::ReadLocalInOwnInitialiser(_) => "RESOLVE001",
+            Error::AlreadyDeclared(_) => "RESOLVE002",
+            Error::ReturnFromTopLevel(_) => "RESOLVE003",
+            Error::ThisOutsideClass(_) => "RESOLVE004",
+            Error::SuperOutsideClass(_) => "RESOLVE005",
+            Error::SuperWithoutSuperclass(_) => "RESOLVE006",
+            Error::BreakOutsideLoop(_) => "RESOLVE007",
+            Error::ContinueOutsideLoop(_) => "RESOLVE008",
+            Error::ClassInheritsFromItself(_) => "RESOLVE009",
+            Error::ReturnValueFromInitialiser(_) => "RESOLVE010",
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::error(self.code(), self.message(), self.span())
+    }
+
+    pub fn display(&self, source: &str, output: &mut dyn Write) -> io::Result<()> {
+        self.display_with(&SourceMap::new(source), Color::Never, output)
+    }
+
+    pub fn display_with(
+        &self,
+        source_map: &SourceMap,
+        color: Color,
+        output: &mut dyn Write,
+    ) -> io::Result<()> {
+        self.diagnostic().render(source_map, color, output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer::Lexer, parser::Parser};
+
+    fn resolve(source: &str) -> Vec<Error> {
+        let lex_result = Lexer::lex(source);
+        assert_eq!(lex_result.errors.len(), 0);
+        let parse_result = Parser::parse(&lex_result.tokens);
+        assert_eq!(parse_result.errors.len(), 0);
+        Resolver::resolve(source, &parse_result.declarations)
+    }
+
+    #[test]
+    fn local_in_own_initialiser() {
+        let errors = resolve("var a = 1; { var a = a; }");
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], Error::ReadLocalInOwnInitialiser(_)));
+
+        // Globals may refer to themselves, since they're resolved at runtime
+        assert_eq!(resolve("var a = 1; var a = a;").len(), 0);
+    }
+
+    #[test]
+    fn duplicate_declaration() {
+        let errors = resolve("fun f(a) { var b; var b; }");
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], Error::AlreadyDeclared(_)));
+    }
+
+    #[test]
+    fn top_level_return() {
+        let errors = resolve("return 1;");
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], Error::ReturnFromTopLevel(_)));
+
+        assert_eq!(resolve("fun f() { return 1; }").len(), 0);
+    }
+
+    #[test]
+    fn return_value_from_initialiser() {
+        let errors = resolve("class A { init() { return nil; } }");
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], Error::ReturnValueFromInitialiser(_)));
+
+        assert_eq!(resolve("class A { init() { return; } }").len(), 0);
+    }
+
+    #[test]
+    fn break_and_continue_outside_loop() {
+        let errors = resolve("break; while (true) { fun f() { continue; } break; }");
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(errors[0], Error::BreakOutsideLoop(_)));
+        assert!(matches!(errors[1], Error::ContinueOutsideLoop(_)));
+
+        assert_eq!(resolve("for (;;) { if (true) continue; break; }").len(), 0);
+    }
+}
@@ -0,0 +1,131 @@
+//! Converting byte offsets into source to the line and column numbers people read
+
+use std::fmt;
+
+use crate::span::Span;
+
+/// An index of where each line of some source starts, for finding the line and column of an
+/// offset without scanning the whole source each time
+#[derive(Debug, Clone)]
+pub struct SourceMap<'a> {
+    source: &'a str,
+    /// The name of the file the source was read from, if it was read from a file
+    file_name: Option<&'a str>,
+    /// The byte offset each line starts at
+    line_starts: Vec<usize>,
+}
+
+/// A position in some source. Both numbers start at 1, and columns count characters rather than
+/// bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+impl<'a> SourceMap<'a> {
+    pub fn new(source: &'a str) -> Self {
+        // A newline at the very end doesn't start a line, so errors at the end of the source are
+        // shown on the last line with something on it
+        let line_starts = std::iter::once(0)
+            .chain(
+                source
+                    .match_indices('\n')
+                    .map(|(index, _)| index + 1)
+                    .filter(|start| *start < source.len()),
+            )
+            .collect();
+        Self {
+            source,
+            file_name: None,
+            line_starts,
+        }
+    }
+
+    /// Index source read from a file, so locations in it are described with the file's name
+    pub fn with_file_name(file_name: &'a str, source: &'a str) -> Self {
+        Self {
+            file_name: Some(file_name),
+            ..Self::new(source)
+        }
+    }
+
+    pub fn source(&self) -> &'a str {
+        self.source
+    }
+
+    pub fn file_name(&self) -> Option<&'a str> {
+        self.file_name
+    }
+
+    /// The line and column of a byte offset. Offsets past the end are on the last line.
+    pub fn location(&self, offset: usize) -> Location {
+        let offset = self.floor_char_boundary(offset);
+        let line_index = self.line_starts.partition_point(|start| *start <= offset) - 1;
+        let line_start = self.line_starts[line_index];
+        Location {
+            line: line_index + 1,
+            column: self.source[line_start..offset].chars().count() + 1,
+        }
+    }
+
+    /// The span of a 1-based line, without its newline
+    pub fn line_span(&self, line: usize) -> Span {
+        let start = self.line_starts[line - 1];
+        let end = match self.line_starts.get(line) {
+            Some(next_line_start) => *next_line_start,
+            None => self.source.len(),
+        };
+        let text = &self.source[start..end];
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        Span::new(start, start + text.len())
+    }
+
+    /// Describe where a span starts as `file:line:column`, or `line:column` for source that
+    /// wasn't read from a file
+    pub fn describe(&self, span: Span) -> String {
+        let location = self.location(span.start);
+        match self.file_name {
+            Some(file_name) => format!("{file_name}:{location}"),
+            None => location.to_string(),
+        }
+    }
+
+    /// Clamp an offset to the source and move it back to the start of the character it's in
+    fn floor_char_boundary(&self, offset: usize) -> usize {
+        let mut offset = offset.min(self.source.len());
+        while !self.source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locations_count_characters_not_bytes() {
+        let source = "var a = \"é\";\nprint a; // ü\r\nprint 😀b;\n";
+        let source_map = SourceMap::new(source);
+        assert_eq!(source_map.location(0), Location { line: 1, column: 1 });
+        let b = source.find('b').unwrap();
+        assert_eq!(source_map.location(b), Location { line: 3, column: 8 });
+        assert_eq!(source_map.line_span(2).slice(source), "print a; // ü");
+        assert_eq!(source_map.line_span(3).slice(source), "print 😀b;");
+        // The end of the source is on the last line
+        assert_eq!(source_map.location(source.len()).line, 3);
+        assert_eq!(
+            SourceMap::with_file_name("test.lox", source).describe(Span::new(b, b + 1)),
+            "test.lox:3:8"
+        );
+    }
+}
@@ -1,6 +1,8 @@
 use std::cmp::{max, min};
 
-#[derive(Debug, Clone, Copy)]
+use crate::source_map::SourceMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Span {
     pub start: usize,
     pub end: usize,
@@ -15,6 +17,12 @@ impl Span {
         &source[self.start..self.end]
     }
 
+    /// The 1-based line of `source` the span starts on. Use a [`SourceMap`] to look up many
+    /// lines in the same source.
+    pub fn line(&self, source: &str) -> usize {
+        SourceMap::new(source).location(self.start).line
+    }
+
     pub fn combine(self, other: Span) -> Span {
         Self::new(min(self.start, other.start), max(self.end, other.end))
     }
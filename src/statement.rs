@@ -4,11 +4,12 @@ use crate::{expression::Expression, lexer::Token};
 
 #[derive(Debug)]
 pub enum Declaration {
-    Function {
+    Class {
         name: Token,
-        parameters: Vec<Token>,
-        body: Rc<Vec<Declaration>>,
+        superclass: Option<Rc<Expression>>,
+        methods: Vec<FunctionDeclaration>,
     },
+    Function(FunctionDeclaration),
     Variable {
         name: Token,
         initialiser: Option<Rc<Expression>>,
@@ -16,6 +17,21 @@ pub enum Declaration {
     Statement(Statement),
 }
 
+#[derive(Debug)]
+pub struct FunctionDeclaration {
+    pub name: Token,
+    pub parameters: Vec<Token>,
+    pub body: Rc<Vec<Declaration>>,
+}
+
+/// The `catch (name) { body }` clause of a `try` statement. `name` is bound to the thrown value, or
+/// to an error value for runtime errors.
+#[derive(Debug)]
+pub struct CatchClause {
+    pub name: Token,
+    pub body: Rc<Vec<Declaration>>,
+}
+
 #[derive(Debug)]
 pub enum Statement {
     Print(Rc<Expression>),
@@ -29,9 +45,33 @@ pub enum Statement {
     While {
         condition: Rc<Expression>,
         body: Box<Statement>,
+        /// The increment clause of a `for` loop, run after the body and after any `continue`
+        increment: Option<Rc<Expression>>,
     },
-    Return {
+    /// `for (var variable in iterable) body`, visiting the elements of a list or the keys of a
+    /// map
+    ForIn {
+        variable: Token,
+        iterable: Rc<Expression>,
+        body: Box<Statement>,
+    },
+    Break(Token),
+    Continue(Token),
+    /// `try { body } catch (name) { ... } finally { ... }`. At least one of the `catch` and
+    /// `finally` clauses is present.
+    Try {
+        keyword: Token,
+        body: Rc<Vec<Declaration>>,
+        catch: Option<CatchClause>,
+        finally: Option<Rc<Vec<Declaration>>>,
+    },
+    Throw {
         keyword: Token,
         value: Rc<Expression>,
     },
+    Return {
+        keyword: Token,
+        /// `None` for a bare `return;`, which returns nil
+        value: Option<Rc<Expression>>,
+    },
 }
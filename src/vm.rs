@@ -0,0 +1,869 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use crate::{
+    diagnostic::closest_name,
+    interpreter::{
+        error::{Error, ErrorValue, Thrown, TraceFrame},
+        native::checked_index,
+        DEFAULT_MAX_CALL_DEPTH,
+    },
+    lexer::{Token, TokenType},
+    limits::{Budget, Limits},
+    map::{Map, MapKey},
+    span::Span,
+    statement::Declaration,
+};
+
+use self::{
+    chunk::OpCode,
+    compiler::Compiler,
+    value::{BoundMethod, Class, Closure, Instance, NativeFunction, Upvalue, Value},
+};
+
+mod chunk;
+mod compiler;
+pub mod disassembler;
+mod native;
+mod value;
+
+/// A stack based virtual machine that executes bytecode compiled from the syntax tree. An
+/// alternative backend to the tree walking [`crate::interpreter::Interpreter`].
+pub struct Vm {
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    globals: HashMap<String, Value>,
+    /// Upvalues that still point at a slot on the stack, so have to be closed when that slot
+    /// is popped
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    /// Where the output of `print` statements is written
+    output: Box<dyn Write>,
+    /// The exception handlers of the try blocks currently running, innermost last
+    handlers: Vec<Handler>,
+    /// Exceptions to throw again once the finally blocks running for them finish
+    rethrows: Vec<Error>,
+    /// How many calls deep Lox code can go before failing with a stack overflow error
+    max_call_depth: usize,
+    budget: Budget,
+}
+
+impl fmt::Debug for Vm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Vm")
+            .field("stack", &self.stack)
+            .field("frames", &self.frames)
+            .field("globals", &self.globals)
+            .field("open_upvalues", &self.open_upvalues)
+            .field("handlers", &self.handlers)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug)]
+struct CallFrame {
+    closure: Rc<Closure>,
+    /// Index of the next instruction to execute
+    ip: usize,
+    /// Index of the first stack slot this call frame can use
+    slots: usize,
+}
+
+/// Where to resume running when an exception is thrown inside a try block
+#[derive(Debug)]
+struct Handler {
+    /// How many call frames there were when the handler was installed
+    frame_count: usize,
+    /// How many values were on the stack when the handler was installed
+    stack_length: usize,
+    /// The instruction to jump to in the call frame that installed the handler
+    target: usize,
+    /// Whether the handler runs a finally block and throws the exception again, rather than
+    /// handing the exception to a catch block
+    rethrows: bool,
+    /// How many exceptions were waiting to be thrown again when the handler was installed
+    rethrow_count: usize,
+}
+
+impl Vm {
+    /// Create a virtual machine that prints to stdout
+    pub fn new() -> Self {
+        Self::with_output(io::stdout())
+    }
+
+    /// Create a virtual machine that writes the output of `print` statements to `output`
+    pub fn with_output(output: impl Write + 'static) -> Self {
+        let mut vm = Self {
+            stack: vec![],
+            frames: vec![],
+            globals: HashMap::new(),
+            open_upvalues: vec![],
+            output: Box::new(output),
+            handlers: vec![],
+            rethrows: vec![],
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            budget: Budget::default(),
+        };
+        vm.define_native("clock", 0, native::clock);
+        vm.define_native("exit", 1, native::exit);
+        vm
+    }
+
+    fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: fn(&[Value], Span) -> Result<Value, Error>,
+    ) {
+        self.globals.insert(
+            name.to_string(),
+            Value::NativeFunction(Rc::new(NativeFunction { arity, function })),
+        );
+    }
+
+    /// Limit how many calls deep Lox code can go before failing with a `Stack overflow` error
+    pub fn set_max_call_depth(&mut self, max_call_depth: usize) {
+        self.max_call_depth = max_call_depth;
+    }
+
+    /// Limit the resources each call to [`Vm::interpret`] can use
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.budget = Budget::new(limits);
+    }
+
+    pub fn interpret(&mut self, source: &str, declarations: &[Declaration]) -> Result<(), Error> {
+        let function = Compiler::compile(source, declarations);
+        self.budget.start();
+        let closure = Rc::new(Closure {
+            function,
+            upvalues: vec![],
+        });
+        self.stack.push(Value::Closure(closure.clone()));
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            slots: 0,
+        });
+
+        let result = self.run().map_err(|error| self.trace(error));
+        if result.is_err() {
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+            self.handlers.clear();
+            self.rethrows.clear();
+        };
+        result
+    }
+
+    /// Attach the function calls running when an error escaped to it
+    fn trace(&self, error: Error) -> Error {
+        error.escaped(self.calls_above(0))
+    }
+
+    /// The calls made from the frame at `frame_index` that are still running, innermost first
+    fn calls_above(&self, frame_index: usize) -> Vec<TraceFrame> {
+        self.frames[frame_index..]
+            .windows(2)
+            .rev()
+            .map(|frames| {
+                let [caller, called] = frames else {
+                    unreachable!("Windows have two frames")
+                };
+                TraceFrame {
+                    function: called.closure.function.name.clone(),
+                    call_span: caller.closure.function.chunk.spans[caller.ip - 1],
+                    source: called.closure.function.source.clone(),
+                }
+            })
+            .collect()
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames
+            .last()
+            .expect("There should always be a call frame while running")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames
+            .last_mut()
+            .expect("There should always be a call frame while running")
+    }
+
+    /// The span of source the currently executing instruction was compiled from
+    fn current_span(&self) -> Span {
+        let frame = self.frame();
+        frame.closure.function.chunk.spans[frame.ip - 1]
+    }
+
+    fn constant(&self, index: usize) -> Value {
+        self.frame().closure.function.chunk.constants[index].clone()
+    }
+
+    fn constant_string(&self, index: usize) -> Rc<str> {
+        match self.constant(index) {
+            Value::String(string) => string,
+            value => unreachable!("Expected a string constant, got {:?}", value),
+        }
+    }
+
+    fn push(&mut self, value: Value) {
+        self.stack.push(value);
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("Tried to pop from an empty stack")
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    /// The token for a name used by the current instruction. Names are always at the end of
+    /// their instruction's span, which can include the object a property is accessed on.
+    fn name_token(&self, name: &str) -> Token {
+        let end = self.current_span().end;
+        Token::new(end - name.len(), end, TokenType::Identifier)
+    }
+
+    /// The error for a global that doesn't exist, suggesting a variable in scope with a similar
+    /// name
+    fn undefined_variable(&self, name: &str) -> Error {
+        let frame = self.frame();
+        // Locals don't have names at runtime, so the compiler picked the closest one already
+        let local = frame
+            .closure
+            .function
+            .chunk
+            .local_suggestions
+            .get(&(frame.ip - 1));
+        let names = self.globals.keys().chain(local).map(String::as_str);
+        Error::VariableDoesntExist {
+            name: self.name_token(name),
+            suggestion: closest_name(name, names).map(String::from),
+        }
+    }
+
+    fn type_error(&self, expected: &str, got: &Value) -> Error {
+        Error::type_error(
+            expected.into(),
+            got.string_description(),
+            self.current_span(),
+        )
+    }
+
+    fn list_index(&self, index: &Value, length: usize) -> Result<usize, Error> {
+        match index {
+            Value::Number(index) => checked_index(*index, length, self.current_span()),
+            _ => Err(self.type_error("Number", index)),
+        }
+    }
+
+    fn map_key(&self, key: &Value) -> Result<MapKey, Error> {
+        key.to_map_key().ok_or_else(|| Error::UnhashableKey {
+            type_name: key.string_description(),
+            span: self.current_span(),
+        })
+    }
+
+    fn pop_number(&mut self) -> Result<f64, Error> {
+        match self.pop() {
+            Value::Number(number) => Ok(number),
+            value => Err(self.type_error("Number", &value)),
+        }
+    }
+
+    fn binary_number_operation(
+        &mut self,
+        operation: impl FnOnce(f64, f64) -> Value,
+    ) -> Result<(), Error> {
+        let right = self.pop_number()?;
+        let left = self.pop_number()?;
+        self.push(operation(left, right));
+        Ok(())
+    }
+
+    /// Run until the script finishes, or throws an error no try block catches
+    fn run(&mut self) -> Result<(), Error> {
+        loop {
+            match self.execute() {
+                Err(error) if error.is_catchable() && !self.handlers.is_empty() => {
+                    self.catch(error)
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Unwind to the innermost exception handler and jump to it. Catch blocks start with the
+    /// value they receive on top of the stack.
+    fn catch(&mut self, error: Error) {
+        let handler = self
+            .handlers
+            .pop()
+            .expect("Errors are only caught when there's a handler");
+        // Remember the calls the error escaped, in case it's thrown again
+        let error = error.escaped(self.calls_above(handler.frame_count - 1));
+        self.frames.truncate(handler.frame_count);
+        self.close_upvalues(handler.stack_length);
+        self.stack.truncate(handler.stack_length);
+        self.rethrows.truncate(handler.rethrow_count);
+        self.frame_mut().ip = handler.target;
+
+        if handler.rethrows {
+            self.rethrows.push(error);
+            return;
+        }
+        let caught = match error.untraced_ref() {
+            Error::Thrown(Thrown { value, .. }) => {
+                match value
+                    .downcast_ref::<Value>()
+                    .expect("The virtual machine only throws its own values")
+                {
+                    // An error thrown again has escaped more calls since it was first caught
+                    Value::Error(caught) => Value::Error(Rc::new(caught.retraced(&error))),
+                    value => value.clone(),
+                }
+            }
+            _ => Value::Error(Rc::new(ErrorValue::new(
+                &error,
+                &self.frame().closure.function.source,
+            ))),
+        };
+        self.push(caught);
+    }
+
+    fn push_handler(&mut self, target: usize, rethrows: bool) {
+        self.handlers.push(Handler {
+            frame_count: self.frames.len(),
+            stack_length: self.stack.len(),
+            target,
+            rethrows,
+            rethrow_count: self.rethrows.len(),
+        });
+    }
+
+    /// Run instructions until the script finishes or an error is thrown
+    fn execute(&mut self) -> Result<(), Error> {
+        loop {
+            self.budget.step(|| {
+                let frame = self
+                    .frames
+                    .last()
+                    .expect("There's always a frame while running");
+                frame.closure.function.chunk.spans[frame.ip]
+            })?;
+            let frame = self.frame_mut();
+            let op_code = frame.closure.function.chunk.code[frame.ip];
+            frame.ip += 1;
+
+            match op_code {
+                OpCode::Constant(index) => {
+                    let constant = self.constant(index);
+                    self.push(constant);
+                }
+                OpCode::Nil => self.push(Value::Nil),
+                OpCode::True => self.push(Value::Boolean(true)),
+                OpCode::False => self.push(Value::Boolean(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal(slot) => {
+                    let value = self.stack[self.frame().slots + slot].clone();
+                    self.push(value);
+                }
+                OpCode::SetLocal(slot) => {
+                    let index = self.frame().slots + slot;
+                    self.stack[index] = self.peek(0).clone();
+                }
+                OpCode::GetGlobal(index) => {
+                    let name = self.constant_string(index);
+                    let value = self
+                        .globals
+                        .get(&*name)
+                        .cloned()
+                        .ok_or_else(|| self.undefined_variable(&name))?;
+                    self.push(value);
+                }
+                OpCode::DefineGlobal(index) => {
+                    let name = self.constant_string(index);
+                    let value = self.pop();
+                    self.globals.insert(name.to_string(), value);
+                }
+                OpCode::SetGlobal(index) => {
+                    let name = self.constant_string(index);
+                    if !self.globals.contains_key(&*name) {
+                        return Err(self.undefined_variable(&name));
+                    };
+                    self.globals.insert(name.to_string(), self.peek(0).clone());
+                }
+                OpCode::GetUpvalue(index) => {
+                    let upvalue = self.frame().closure.upvalues[index].clone();
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.push(value);
+                }
+                OpCode::SetUpvalue(index) => {
+                    let upvalue = self.frame().closure.upvalues[index].clone();
+                    let value = self.peek(0).clone();
+                    match &mut *upvalue.borrow_mut() {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    };
+                }
+                OpCode::GetProperty(index) => {
+                    let name = self.constant_string(index);
+                    if let Value::List(_) | Value::Map(_) = self.peek(0) {
+                        let method = native::method(self.peek(0), &name)
+                            .ok_or_else(|| Error::UndefinedProperty(self.name_token(&name)))?;
+                        self.pop();
+                        self.push(Value::NativeMethod(Rc::new(method)));
+                        continue;
+                    }
+                    if let Value::Error(error) = self.peek(0) {
+                        let property = match name.as_ref() {
+                            "message" => Value::String(error.message.as_str().into()),
+                            "line" => Value::Number(error.line as f64),
+                            _ => return Err(Error::UndefinedProperty(self.name_token(&name))),
+                        };
+                        self.pop();
+                        self.push(property);
+                        continue;
+                    }
+                    let Value::Instance(instance) = self.peek(0).clone() else {
+                        return Err(Error::OnlyInstancesHaveProperties(self.current_span()));
+                    };
+
+                    let field = instance.borrow().fields.get(&*name).cloned();
+                    if let Some(field) = field {
+                        self.pop();
+                        self.push(field);
+                    } else {
+                        let class = instance.borrow().class.clone();
+                        self.bind_method(&class, &name)?;
+                    }
+                }
+                OpCode::SetProperty(index) => {
+                    let Value::Instance(instance) = self.peek(1).clone() else {
+                        return Err(Error::OnlyInstancesHaveProperties(self.current_span()));
+                    };
+                    let name = self.constant_string(index);
+                    let value = self.pop();
+                    instance
+                        .borrow_mut()
+                        .fields
+                        .insert(name.to_string(), value.clone());
+                    self.pop();
+                    self.push(value);
+                }
+                OpCode::GetSuper(index) => {
+                    let name = self.constant_string(index);
+                    let Value::Class(superclass) = self.pop() else {
+                        return Err(Error::SuperclassMustBeAClass(self.current_span()));
+                    };
+                    self.bind_method(&superclass, &name)?;
+                }
+                OpCode::List(count) => {
+                    self.budget.check_size(count, self.current_span())?;
+                    let elements = self.stack.split_off(self.stack.len() - count);
+                    self.push(Value::List(Rc::new(RefCell::new(elements))));
+                }
+                OpCode::CheckKey => {
+                    self.map_key(self.peek(0))?;
+                }
+                OpCode::Map(count) => {
+                    let mut map = Map::default();
+                    let entries = self.stack.split_off(self.stack.len() - count * 2);
+                    for entry in entries.chunks(2) {
+                        map.insert(self.map_key(&entry[0])?, entry[1].clone());
+                    }
+                    self.budget.check_size(map.len(), self.current_span())?;
+                    self.push(Value::Map(Rc::new(RefCell::new(map))));
+                }
+                OpCode::GetIndex => {
+                    let index = self.pop();
+                    let value = match self.pop() {
+                        Value::List(list) => {
+                            let list = list.borrow();
+                            list[self.list_index(&index, list.len())?].clone()
+                        }
+                        // Missing keys read as nil
+                        Value::Map(map) => map
+                            .borrow()
+                            .get(&self.map_key(&index)?)
+                            .cloned()
+                            .unwrap_or(Value::Nil),
+                        _ => return Err(Error::NotIndexable(self.current_span())),
+                    };
+                    self.push(value);
+                }
+                OpCode::SetIndex => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    match self.pop() {
+                        Value::List(list) => {
+                            let index = self.list_index(&index, list.borrow().len())?;
+                            list.borrow_mut()[index] = value.clone();
+                        }
+                        Value::Map(map) => {
+                            let key = self.map_key(&index)?;
+                            let mut map = map.borrow_mut();
+                            if !map.contains_key(&key) {
+                                self.budget.check_size(map.len() + 1, self.current_span())?;
+                            }
+                            map.insert(key, value.clone());
+                        }
+                        _ => return Err(Error::NotIndexable(self.current_span())),
+                    }
+                    self.push(value);
+                }
+                OpCode::Iterate => {
+                    let list = match self.pop() {
+                        list @ Value::List(_) => list,
+                        // Maps are iterated over a snapshot of their keys
+                        Value::Map(map) => {
+                            let keys = map.borrow().keys().map(Value::from_map_key).collect();
+                            Value::List(Rc::new(RefCell::new(keys)))
+                        }
+                        _ => return Err(Error::NotIterable(self.current_span())),
+                    };
+                    self.push(list);
+                }
+                OpCode::HasNext(slot) => {
+                    let base = self.frame().slots;
+                    let Value::List(list) = &self.stack[base + slot] else {
+                        unreachable!("For-in loops iterate over lists");
+                    };
+                    let Value::Number(index) = self.stack[base + slot + 1] else {
+                        unreachable!("For-in loop indices are numbers");
+                    };
+                    let has_next = (index as usize) < list.borrow().len();
+                    self.push(Value::Boolean(has_next));
+                }
+                OpCode::Next(slot) => {
+                    let base = self.frame().slots;
+                    let Value::List(list) = &self.stack[base + slot] else {
+                        unreachable!("For-in loops iterate over lists");
+                    };
+                    let Value::Number(index) = self.stack[base + slot + 1] else {
+                        unreachable!("For-in loop indices are numbers");
+                    };
+                    let element = list.borrow()[index as usize].clone();
+                    self.stack[base + slot + 1] = Value::Number(index + 1.0);
+                    self.push(element);
+                }
+                OpCode::Equal => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.push(Value::Boolean(left.equals(&right)));
+                }
+                OpCode::Greater => {
+                    self.binary_number_operation(|left, right| Value::Boolean(left > right))?
+                }
+                OpCode::Less => {
+                    self.binary_number_operation(|left, right| Value::Boolean(left < right))?
+                }
+                OpCode::Add => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let result = match (&left, &right) {
+                        (Value::Number(left), Value::Number(right)) => Value::Number(left + right),
+                        (Value::String(left), Value::String(right)) => {
+                            let string = format!("{left}{right}");
+                            self.budget.check_size(string.len(), self.current_span())?;
+                            Value::String(string.into())
+                        }
+                        (Value::Number(_), _) => return Err(self.type_error("Number", &right)),
+                        (Value::String(_), _) => return Err(self.type_error("String", &right)),
+                        _ => return Err(self.type_error("Number", &left)),
+                    };
+                    self.push(result);
+                }
+                OpCode::Subtract => {
+                    self.binary_number_operation(|left, right| Value::Number(left - right))?
+                }
+                OpCode::Multiply => {
+                    self.binary_number_operation(|left, right| Value::Number(left * right))?
+                }
+                OpCode::Divide => {
+                    self.binary_number_operation(|left, right| Value::Number(left / right))?
+                }
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.push(Value::Boolean(value.is_falsey()));
+                }
+                OpCode::Negate => {
+                    let number = self.pop_number()?;
+                    self.push(Value::Number(-number));
+                }
+                OpCode::Print => {
+                    let value = self.pop();
+                    writeln!(self.output, "{value}")
+                        .map_err(|error| Error::Output(error, self.current_span()))?;
+                }
+                OpCode::Concatenate(count) => {
+                    let parts = self.stack.split_off(self.stack.len() - count);
+                    let string = parts.iter().map(Value::to_string).collect::<String>();
+                    self.budget.check_size(string.len(), self.current_span())?;
+                    self.push(Value::String(string.into()));
+                }
+                OpCode::PushHandler(target) => self.push_handler(target, false),
+                OpCode::PushFinally(target) => self.push_handler(target, true),
+                OpCode::PopHandler => {
+                    self.handlers.pop();
+                }
+                OpCode::Throw => {
+                    let value = self.pop();
+                    // Rethrown errors are reported where they originally happened, inside the
+                    // calls they had escaped before they were caught
+                    let (message, span, trace) = match &value {
+                        Value::Error(error) => {
+                            (error.message.clone(), error.span, error.trace.clone())
+                        }
+                        value => (
+                            format!("Uncaught exception: {value}"),
+                            self.current_span(),
+                            vec![],
+                        ),
+                    };
+                    let error = Error::Thrown(Thrown {
+                        value: Rc::new(value),
+                        message,
+                        span,
+                    });
+                    return Err(error.escaped(trace));
+                }
+                OpCode::Rethrow => {
+                    return Err(self
+                        .rethrows
+                        .pop()
+                        .expect("Rethrow only runs after a finally handler caught an exception"))
+                }
+                OpCode::Jump(target) => self.frame_mut().ip = target,
+                OpCode::JumpIfFalse(target) => {
+                    if self.peek(0).is_falsey() {
+                        self.frame_mut().ip = target;
+                    }
+                }
+                OpCode::Call(argument_count) => {
+                    let callee = self.peek(argument_count).clone();
+                    self.call_value(callee, argument_count)?;
+                }
+                OpCode::Closure(index) => {
+                    let Value::Function(function) = self.constant(index) else {
+                        unreachable!("Closure operand should always be a function constant");
+                    };
+                    let upvalues = function
+                        .upvalues
+                        .iter()
+                        .map(|upvalue| {
+                            if upvalue.is_local {
+                                self.capture_upvalue(self.frame().slots + upvalue.index)
+                            } else {
+                                self.frame().closure.upvalues[upvalue.index].clone()
+                            }
+                        })
+                        .collect();
+                    self.push(Value::Closure(Rc::new(Closure { function, upvalues })));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self
+                        .frames
+                        .pop()
+                        .expect("There should always be a call frame while running");
+                    self.close_upvalues(frame.slots);
+                    self.stack.truncate(frame.slots);
+
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    };
+                    self.push(result);
+                }
+                OpCode::Class(index) => {
+                    let name = self.constant_string(index);
+                    self.push(Value::Class(Rc::new(RefCell::new(Class {
+                        name: name.to_string(),
+                        methods: HashMap::new(),
+                    }))));
+                }
+                OpCode::Inherit => {
+                    let Value::Class(superclass) = self.peek(1) else {
+                        return Err(Error::SuperclassMustBeAClass(self.current_span()));
+                    };
+                    let Value::Class(subclass) = self.peek(0) else {
+                        unreachable!("The class being declared should be on top of the stack");
+                    };
+                    // Copy the methods down, so subclass methods declared later override them
+                    let methods = superclass.borrow().methods.clone();
+                    subclass.borrow_mut().methods.extend(methods);
+                    self.pop();
+                }
+                OpCode::Method(index) => {
+                    let name = self.constant_string(index);
+                    let Value::Closure(method) = self.pop() else {
+                        unreachable!("Method should always be a closure");
+                    };
+                    let Value::Class(class) = self.peek(0) else {
+                        unreachable!("The class being declared should be below its method");
+                    };
+                    class.borrow_mut().methods.insert(name.to_string(), method);
+                }
+            }
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, argument_count: usize) -> Result<(), Error> {
+        let callee_slot = self.stack.len() - argument_count - 1;
+        match callee {
+            Value::Closure(closure) => self.call(closure, argument_count),
+            Value::Class(class) => {
+                self.stack[callee_slot] = Value::Instance(Rc::new(RefCell::new(Instance {
+                    class: class.clone(),
+                    fields: HashMap::new(),
+                })));
+                let initialiser = class.borrow().methods.get("init").cloned();
+                match initialiser {
+                    Some(initialiser) => self.call(initialiser, argument_count),
+                    None if argument_count != 0 => Err(Error::Arity {
+                        got: argument_count,
+                        expected: 0,
+                        call_span: self.current_span(),
+                        declaration_span: None,
+                    }),
+                    None => Ok(()),
+                }
+            }
+            Value::NativeFunction(native_function) => {
+                if native_function.arity != argument_count {
+                    return Err(Error::Arity {
+                        got: argument_count,
+                        expected: native_function.arity,
+                        call_span: self.current_span(),
+                        declaration_span: None,
+                    });
+                };
+                let result = (native_function.function)(
+                    &self.stack[callee_slot + 1..],
+                    self.current_span(),
+                )?;
+                self.stack.truncate(callee_slot);
+                self.push(result);
+                Ok(())
+            }
+            Value::NativeMethod(native_method) => {
+                if native_method.arity != argument_count {
+                    return Err(Error::Arity {
+                        got: argument_count,
+                        expected: native_method.arity,
+                        call_span: self.current_span(),
+                        declaration_span: None,
+                    });
+                };
+                let result = (native_method.function)(
+                    &native_method.receiver,
+                    &self.stack[callee_slot + 1..],
+                    self.budget.max_collection_size(),
+                    self.current_span(),
+                )?;
+                self.stack.truncate(callee_slot);
+                self.push(result);
+                Ok(())
+            }
+            Value::BoundMethod(bound_method) => {
+                self.stack[callee_slot] = bound_method.receiver.clone();
+                self.call(bound_method.method.clone(), argument_count)
+            }
+            _ => Err(Error::NotCallable(self.current_span())),
+        }
+    }
+
+    fn call(&mut self, closure: Rc<Closure>, argument_count: usize) -> Result<(), Error> {
+        if closure.function.arity != argument_count {
+            // Functions compiled by earlier runs point into source that isn't being shown
+            let declaration_span = closure.function.name_span.filter(|_| {
+                Rc::ptr_eq(
+                    &closure.function.source,
+                    &self.frame().closure.function.source,
+                )
+            });
+            return Err(Error::Arity {
+                got: argument_count,
+                expected: closure.function.arity,
+                call_span: self.current_span(),
+                declaration_span,
+            });
+        };
+        // The top level script has a frame but isn't a call
+        if self.frames.len() > self.max_call_depth {
+            return Err(Error::StackOverflow(self.current_span()));
+        }
+
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            slots: self.stack.len() - argument_count - 1,
+        });
+        Ok(())
+    }
+
+    /// Replace the instance on top of the stack with the named method bound to it
+    fn bind_method(&mut self, class: &Rc<RefCell<Class>>, name: &str) -> Result<(), Error> {
+        let method = class
+            .borrow()
+            .methods
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::UndefinedProperty(self.name_token(name)))?;
+        let receiver = self.pop();
+        self.push(Value::BoundMethod(Rc::new(BoundMethod {
+            receiver,
+            method,
+        })));
+        Ok(())
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let existing = self
+            .open_upvalues
+            .iter()
+            .find(|upvalue| matches!(&*upvalue.borrow(), Upvalue::Open(open) if *open == slot));
+        if let Some(existing) = existing {
+            return existing.clone();
+        };
+
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    /// Close every open upvalue pointing at the given stack slot or above
+    fn close_upvalues(&mut self, from_slot: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let slot = match &*upvalue.borrow() {
+                Upvalue::Open(slot) if *slot >= from_slot => *slot,
+                _ => return true,
+            };
+            *upvalue.borrow_mut() = Upvalue::Closed(stack[slot].clone());
+            false
+        });
+    }
+}
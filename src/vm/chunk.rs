@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use crate::span::Span;
+
+use super::value::Value;
+
+/// A single bytecode instruction. Operands are stored inline rather than as separate bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpCode {
+    /// Push the constant at the given index in the constant pool
+    Constant(usize),
+    Nil,
+    True,
+    False,
+    Pop,
+    /// Push the local in the given stack slot of the current call frame
+    GetLocal(usize),
+    SetLocal(usize),
+    /// Operand is the index of the variable's name in the constant pool
+    GetGlobal(usize),
+    DefineGlobal(usize),
+    SetGlobal(usize),
+    /// Operand is the index into the current closure's upvalues
+    GetUpvalue(usize),
+    SetUpvalue(usize),
+    /// Operand is the index of the property's name in the constant pool
+    GetProperty(usize),
+    SetProperty(usize),
+    GetSuper(usize),
+    /// Create a list from the given number of values on top of the stack
+    List(usize),
+    /// Check the value on top of the stack can be used as a map key, so a map literal with an
+    /// unhashable key fails at that key
+    CheckKey,
+    /// Create a map from the given number of key value pairs on top of the stack
+    Map(usize),
+    GetIndex,
+    SetIndex,
+    /// Replace the list or map on top of the stack with the list a for-in loop iterates over
+    Iterate,
+    /// Push whether the for-in loop whose list is in the given stack slot has more elements.
+    /// The index of the next element is in the slot after it.
+    HasNext(usize),
+    /// Push the next element of the for-in loop whose list is in the given stack slot
+    Next(usize),
+    Equal,
+    Greater,
+    Less,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    /// Stringify the given number of values on top of the stack and join them into one string
+    Concatenate(usize),
+    /// Jump to the given instruction offset
+    Jump(usize),
+    /// Jump to the given instruction offset if the value on top of the stack is falsey. Doesn't
+    /// pop the value.
+    JumpIfFalse(usize),
+    /// Install an exception handler that jumps to the given instruction offset with the thrown
+    /// value on top of the stack
+    PushHandler(usize),
+    /// Install an exception handler that jumps to the given instruction offset, keeping the
+    /// exception to be thrown again by `Rethrow` once the finally block has run
+    PushFinally(usize),
+    PopHandler,
+    /// Throw the value on top of the stack to the innermost exception handler
+    Throw,
+    /// Throw the exception kept by the handler installed with `PushFinally` again
+    Rethrow,
+    /// Call the value below the given number of arguments on the stack
+    Call(usize),
+    /// Operand is the index of a function in the constant pool to wrap in a closure
+    Closure(usize),
+    CloseUpvalue,
+    Return,
+    /// Operand is the index of the class's name in the constant pool
+    Class(usize),
+    Inherit,
+    /// Operand is the index of the method's name in the constant pool
+    Method(usize),
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<OpCode>,
+    pub constants: Vec<Value>,
+    /// The span of source each instruction was compiled from, used for runtime errors
+    pub spans: Vec<Span>,
+    /// The source line each instruction was compiled from
+    pub lines: Vec<usize>,
+    /// For global variable instructions whose name is like a local in scope, the local's name,
+    /// so the error if the global doesn't exist can suggest it
+    pub local_suggestions: HashMap<usize, String>,
+}
+
+impl Chunk {
+    /// Append an instruction, returning its offset
+    pub fn write(&mut self, op_code: OpCode, span: Span, line: usize) -> usize {
+        self.code.push(op_code);
+        self.spans.push(span);
+        self.lines.push(line);
+        self.code.len() - 1
+    }
+
+    /// Add a value to the constant pool, returning its index
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+}
@@ -0,0 +1,912 @@
+use std::rc::Rc;
+
+use crate::{
+    diagnostic::closest_name,
+    expression::{
+        AssignmentExpression, BinaryExpression, CallExpression, Expression, FunctionExpression,
+        GetExpression, GroupingExpression, IndexExpression, InterpolationExpression,
+        ListExpression, LiteralExpression, LogicalExpression, MapExpression, SetExpression,
+        SetIndexExpression, SuperExpression, ThisExpression, UnaryExpression, VariableExpression,
+    },
+    lexer::{Token, TokenType},
+    span::Span,
+    statement::{CatchClause, Declaration, FunctionDeclaration, Statement},
+};
+
+use super::{
+    chunk::OpCode,
+    value::{Function, UpvalueDescriptor, Value},
+};
+
+/// Compiles a syntax tree into bytecode for the [`super::Vm`].
+///
+/// Expects the tree to have already been checked by the resolver, so doesn't report any errors
+/// of its own.
+pub struct Compiler<'a> {
+    source: &'a str,
+    /// The source again, to be kept by every function compiled from it
+    shared_source: Rc<str>,
+    /// Byte offset of the start of each line in the source
+    line_starts: Vec<usize>,
+    /// Stack of the functions currently being compiled. The top level script is at the bottom
+    functions: Vec<FunctionState>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    Script,
+    Function,
+    Method,
+    Initialiser,
+}
+
+struct FunctionState {
+    function: Function,
+    function_type: FunctionType,
+    locals: Vec<Local>,
+    scope_depth: usize,
+    /// The loops currently being compiled, innermost last
+    loops: Vec<Loop>,
+    /// The try blocks currently being compiled, innermost last
+    try_blocks: Vec<TryBlock>,
+}
+
+/// Code running with an exception handler installed. Jumping out of it with `break`, `continue`
+/// or `return` has to remove the handler and run the finally block, if there is one.
+struct TryBlock {
+    /// How many loops were being compiled when the try block started, so `break` and
+    /// `continue` know which try blocks they jump out of
+    loop_count: usize,
+    finally: Option<Rc<Vec<Declaration>>>,
+}
+
+struct Loop {
+    /// The scope depth outside the loop body. Locals deeper than this are discarded when
+    /// jumping out of the body
+    scope_depth: usize,
+    /// Jumps emitted for `break` statements, to be patched to the end of the loop
+    breaks: Vec<usize>,
+    /// Jumps emitted for `continue` statements, to be patched to the increment clause
+    continues: Vec<usize>,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    /// Whether a closure captures this local, in which case it has to be moved off the stack
+    /// when it goes out of scope
+    is_captured: bool,
+}
+
+impl<'a> Compiler<'a> {
+    pub fn compile(source: &'a str, declarations: &[Declaration]) -> Rc<Function> {
+        let line_starts = std::iter::once(0)
+            .chain(
+                source
+                    .bytes()
+                    .enumerate()
+                    .filter(|(_, byte)| *byte == b'\n')
+                    .map(|(index, _)| index + 1),
+            )
+            .collect();
+
+        let mut compiler = Compiler {
+            source,
+            shared_source: source.into(),
+            line_starts,
+            functions: vec![],
+        };
+
+        compiler.begin_function(String::new(), FunctionType::Script);
+        compiler.compile_declarations(declarations);
+        Rc::new(compiler.end_function(Span::new(source.len(), source.len())))
+    }
+
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions
+            .last_mut()
+            .expect("There should always be a function being compiled")
+    }
+
+    fn line_of(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|start| *start <= offset)
+    }
+
+    fn emit(&mut self, op_code: OpCode, span: Span) -> usize {
+        let line = self.line_of(span.start);
+        self.current().function.chunk.write(op_code, span, line)
+    }
+
+    /// The span of the most recently emitted instruction
+    fn last_span(&mut self) -> Span {
+        self.current()
+            .function
+            .chunk
+            .spans
+            .last()
+            .copied()
+            .unwrap_or(Span::new(0, 0))
+    }
+
+    fn emit_constant(&mut self, value: Value, span: Span) {
+        let constant = self.current().function.chunk.add_constant(value);
+        self.emit(OpCode::Constant(constant), span);
+    }
+
+    fn identifier_constant(&mut self, name: &str) -> usize {
+        self.current()
+            .function
+            .chunk
+            .add_constant(Value::String(name.into()))
+    }
+
+    /// Point the jump instruction at the given offset to the next instruction to be emitted
+    fn patch_jump(&mut self, offset: usize) {
+        let target = self.current().function.chunk.code.len();
+        let code = &mut self.current().function.chunk.code;
+        code[offset] = match code[offset] {
+            OpCode::Jump(_) => OpCode::Jump(target),
+            OpCode::JumpIfFalse(_) => OpCode::JumpIfFalse(target),
+            OpCode::PushHandler(_) => OpCode::PushHandler(target),
+            OpCode::PushFinally(_) => OpCode::PushFinally(target),
+            op_code => unreachable!("Tried to patch non jump instruction {:?}", op_code),
+        };
+    }
+
+    fn begin_function(&mut self, name: String, function_type: FunctionType) {
+        // The first stack slot of every call frame holds the function being called, or the
+        // instance for methods
+        let slot_zero = match function_type {
+            FunctionType::Method | FunctionType::Initialiser => "this",
+            FunctionType::Script | FunctionType::Function => "",
+        };
+        self.functions.push(FunctionState {
+            function: Function {
+                name,
+                source: self.shared_source.clone(),
+                ..Default::default()
+            },
+            function_type,
+            locals: vec![Local {
+                name: slot_zero.into(),
+                depth: 0,
+                is_captured: false,
+            }],
+            scope_depth: if function_type == FunctionType::Script {
+                0
+            } else {
+                1
+            },
+            loops: vec![],
+            try_blocks: vec![],
+        });
+    }
+
+    /// Finish compiling the current function. The implicit return is attributed to the last
+    /// instruction's span, or the given span if the function is empty
+    fn end_function(&mut self, span: Span) -> Function {
+        let span = if self.current().function.chunk.code.is_empty() {
+            span
+        } else {
+            self.last_span()
+        };
+        self.emit_implicit_return(span);
+        self.functions
+            .pop()
+            .expect("There should always be a function being compiled")
+            .function
+    }
+
+    fn emit_implicit_return(&mut self, span: Span) {
+        if self.current().function_type == FunctionType::Initialiser {
+            self.emit(OpCode::GetLocal(0), span);
+        } else {
+            self.emit(OpCode::Nil, span);
+        }
+        self.emit(OpCode::Return, span);
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self, span: Span) {
+        self.current().scope_depth -= 1;
+        let scope_depth = self.current().scope_depth;
+        self.discard_locals(scope_depth, span);
+        while let Some(local) = self.current().locals.last()
+            && local.depth > scope_depth
+        {
+            self.current().locals.pop();
+        }
+    }
+
+    /// Emit instructions to remove every local deeper than `scope_depth` from the stack, without
+    /// forgetting about them at compile time
+    fn discard_locals(&mut self, scope_depth: usize, span: Span) {
+        let op_codes: Vec<OpCode> = self
+            .current()
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > scope_depth)
+            .map(|local| {
+                if local.is_captured {
+                    OpCode::CloseUpvalue
+                } else {
+                    OpCode::Pop
+                }
+            })
+            .collect();
+        for op_code in op_codes {
+            self.emit(op_code, span);
+        }
+    }
+
+    fn add_local(&mut self, name: &str) {
+        let depth = self.current().scope_depth;
+        self.current().locals.push(Local {
+            name: name.into(),
+            depth,
+            is_captured: false,
+        });
+    }
+
+    fn is_global_scope(&mut self) -> bool {
+        self.current().scope_depth == 0
+    }
+
+    fn resolve_local(&self, function_index: usize, name: &str) -> Option<usize> {
+        self.functions[function_index]
+            .locals
+            .iter()
+            .rposition(|local| local.name == name)
+    }
+
+    fn resolve_upvalue(&mut self, function_index: usize, name: &str) -> Option<usize> {
+        if function_index == 0 {
+            return None;
+        };
+        let enclosing = function_index - 1;
+
+        if let Some(local) = self.resolve_local(enclosing, name) {
+            self.functions[enclosing].locals[local].is_captured = true;
+            return Some(self.add_upvalue(function_index, local, true));
+        };
+
+        let upvalue = self.resolve_upvalue(enclosing, name)?;
+        Some(self.add_upvalue(function_index, upvalue, false))
+    }
+
+    fn add_upvalue(&mut self, function_index: usize, index: usize, is_local: bool) -> usize {
+        let descriptor = UpvalueDescriptor { is_local, index };
+        let upvalues = &mut self.functions[function_index].function.upvalues;
+        if let Some(existing) = upvalues.iter().position(|upvalue| *upvalue == descriptor) {
+            return existing;
+        };
+        upvalues.push(descriptor);
+        upvalues.len() - 1
+    }
+
+    /// Emit an instruction to read the variable with the given name, or to assign to it if a
+    /// value is given
+    fn named_variable(&mut self, name: &str, span: Span, value: Option<&Rc<Expression>>) {
+        let function_index = self.functions.len() - 1;
+        let (get, set) = if let Some(local) = self.resolve_local(function_index, name) {
+            (OpCode::GetLocal(local), OpCode::SetLocal(local))
+        } else if let Some(upvalue) = self.resolve_upvalue(function_index, name) {
+            (OpCode::GetUpvalue(upvalue), OpCode::SetUpvalue(upvalue))
+        } else {
+            let constant = self.identifier_constant(name);
+            (OpCode::GetGlobal(constant), OpCode::SetGlobal(constant))
+        };
+
+        let offset = if let Some(value) = value {
+            self.compile_expression(value);
+            self.emit(set, span)
+        } else {
+            self.emit(get, span)
+        };
+
+        if let OpCode::GetGlobal(_) = get {
+            // Names of locals are gone at runtime, so find one the global might be a typo of now
+            let locals = self
+                .functions
+                .iter()
+                .flat_map(|function| &function.locals)
+                .map(|local| local.name.as_str());
+            if let Some(local) = closest_name(name, locals).map(String::from) {
+                self.current()
+                    .function
+                    .chunk
+                    .local_suggestions
+                    .insert(offset, local);
+            };
+        };
+    }
+
+    /// Define a variable whose value is on top of the stack
+    fn define_variable(&mut self, name: &str, span: Span) {
+        if self.is_global_scope() {
+            let constant = self.identifier_constant(name);
+            self.emit(OpCode::DefineGlobal(constant), span);
+        } else {
+            self.add_local(name);
+        }
+    }
+
+    fn compile_declarations(&mut self, declarations: &[Declaration]) {
+        declarations
+            .iter()
+            .for_each(|declaration| self.compile_declaration(declaration));
+    }
+
+    fn compile_declaration(&mut self, declaration: &Declaration) {
+        match declaration {
+            Declaration::Class {
+                name,
+                superclass,
+                methods,
+            } => self.compile_class(name.span, superclass, methods),
+            Declaration::Function(function) => {
+                let name = function.name.span.slice(self.source);
+                // Declare local functions before compiling the body, so they can recurse
+                if !self.is_global_scope() {
+                    self.add_local(name);
+                }
+                self.compile_function(
+                    name,
+                    function.name.span,
+                    &function.parameters,
+                    &function.body,
+                    FunctionType::Function,
+                );
+                if self.is_global_scope() {
+                    self.define_variable(name, function.name.span);
+                }
+            }
+            Declaration::Variable { name, initialiser } => {
+                if let Some(initialiser) = initialiser {
+                    self.compile_expression(initialiser);
+                } else {
+                    self.emit(OpCode::Nil, name.span);
+                }
+                self.define_variable(name.span.slice(self.source), name.span);
+            }
+            Declaration::Statement(statement) => self.compile_statement(statement),
+        }
+    }
+
+    fn compile_class(
+        &mut self,
+        name_span: Span,
+        superclass: &Option<Rc<Expression>>,
+        methods: &[FunctionDeclaration],
+    ) {
+        let name = name_span.slice(self.source);
+        let name_constant = self.identifier_constant(name);
+        if !self.is_global_scope() {
+            self.add_local(name);
+        }
+        self.emit(OpCode::Class(name_constant), name_span);
+        if self.is_global_scope() {
+            self.define_variable(name, name_span);
+        }
+
+        if let Some(superclass) = superclass {
+            // Keep the superclass in a local named `super` that methods can capture
+            self.compile_expression(superclass);
+            self.begin_scope();
+            self.add_local("super");
+            self.named_variable(name, name_span, None);
+            self.emit(OpCode::Inherit, superclass.span());
+        }
+
+        self.named_variable(name, name_span, None);
+        for method in methods {
+            let method_name = method.name.span.slice(self.source);
+            let function_type = if method_name == "init" {
+                FunctionType::Initialiser
+            } else {
+                FunctionType::Method
+            };
+            self.compile_function(
+                method_name,
+                method.name.span,
+                &method.parameters,
+                &method.body,
+                function_type,
+            );
+            let method_constant = self.identifier_constant(method_name);
+            self.emit(OpCode::Method(method_constant), method.name.span);
+        }
+        self.emit(OpCode::Pop, name_span);
+
+        if superclass.is_some() {
+            self.end_scope(name_span);
+        }
+    }
+
+    /// Compile a function and emit an instruction to create a closure from it
+    fn compile_function(
+        &mut self,
+        name: &str,
+        name_span: Span,
+        parameters: &[Token],
+        body: &[Declaration],
+        function_type: FunctionType,
+    ) {
+        self.begin_function(name.to_string(), function_type);
+        self.current().function.arity = parameters.len();
+        for parameter in parameters {
+            self.add_local(parameter.span.slice(self.source));
+        }
+        self.compile_declarations(body);
+        let mut compiled = self.end_function(name_span);
+        compiled.name_span = Some(name_span);
+
+        let constant = self
+            .current()
+            .function
+            .chunk
+            .add_constant(Value::Function(Rc::new(compiled)));
+        self.emit(OpCode::Closure(constant), name_span);
+    }
+
+    fn compile_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Print(expression) => {
+                self.compile_expression(expression);
+                self.emit(OpCode::Print, expression.span());
+            }
+            Statement::Expression(expression) => {
+                self.compile_expression(expression);
+                self.emit(OpCode::Pop, expression.span());
+            }
+            Statement::Block(declarations) => self.compile_block(declarations),
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let span = condition.span();
+                self.compile_expression(condition);
+                let then_jump = self.emit(OpCode::JumpIfFalse(0), span);
+                self.emit(OpCode::Pop, span);
+                self.compile_statement(then_branch);
+                let else_jump = self.emit(OpCode::Jump(0), span);
+                self.patch_jump(then_jump);
+                self.emit(OpCode::Pop, span);
+                if let Some(else_branch) = else_branch {
+                    self.compile_statement(else_branch);
+                }
+                self.patch_jump(else_jump);
+            }
+            Statement::While {
+                condition,
+                body,
+                increment,
+            } => {
+                let span = condition.span();
+                let loop_start = self.current().function.chunk.code.len();
+                self.compile_expression(condition);
+                let exit_jump = self.emit(OpCode::JumpIfFalse(0), span);
+                self.emit(OpCode::Pop, span);
+
+                let scope_depth = self.current().scope_depth;
+                self.current().loops.push(Loop {
+                    scope_depth,
+                    breaks: vec![],
+                    continues: vec![],
+                });
+                self.compile_statement(body);
+                let Loop {
+                    breaks, continues, ..
+                } = self
+                    .current()
+                    .loops
+                    .pop()
+                    .expect("The loop being compiled should be on the stack");
+
+                for continue_jump in continues {
+                    self.patch_jump(continue_jump);
+                }
+                if let Some(increment) = increment {
+                    self.compile_expression(increment);
+                    self.emit(OpCode::Pop, increment.span());
+                }
+                self.emit(OpCode::Jump(loop_start), span);
+                self.patch_jump(exit_jump);
+                self.emit(OpCode::Pop, span);
+                for break_jump in breaks {
+                    self.patch_jump(break_jump);
+                }
+            }
+            Statement::ForIn {
+                variable,
+                iterable,
+                body,
+            } => {
+                let span = iterable.span();
+                // The list being iterated over and the index of the next element live in hidden
+                // locals, named so they can't clash with a Lox variable
+                self.begin_scope();
+                self.compile_expression(iterable);
+                self.emit(OpCode::Iterate, span);
+                self.add_local(" sequence");
+                self.emit_constant(Value::Number(0.0), span);
+                self.add_local(" index");
+                let slot = self.current().locals.len() - 2;
+
+                let loop_start = self.current().function.chunk.code.len();
+                self.emit(OpCode::HasNext(slot), span);
+                let exit_jump = self.emit(OpCode::JumpIfFalse(0), span);
+                self.emit(OpCode::Pop, span);
+
+                let scope_depth = self.current().scope_depth;
+                self.current().loops.push(Loop {
+                    scope_depth,
+                    breaks: vec![],
+                    continues: vec![],
+                });
+                // Each iteration gets a fresh variable, so closures capture that iteration's
+                // element
+                self.begin_scope();
+                self.emit(OpCode::Next(slot), variable.span);
+                self.add_local(variable.span.slice(self.source));
+                self.compile_statement(body);
+                self.end_scope(span);
+                let Loop {
+                    breaks, continues, ..
+                } = self
+                    .current()
+                    .loops
+                    .pop()
+                    .expect("The loop being compiled should be on the stack");
+
+                for continue_jump in continues {
+                    self.patch_jump(continue_jump);
+                }
+                self.emit(OpCode::Jump(loop_start), span);
+                self.patch_jump(exit_jump);
+                self.emit(OpCode::Pop, span);
+                for break_jump in breaks {
+                    self.patch_jump(break_jump);
+                }
+                self.end_scope(span);
+            }
+            Statement::Try {
+                keyword,
+                body,
+                catch,
+                finally,
+            } => self.compile_try(keyword.span, body, catch, finally),
+            Statement::Throw { keyword, value } => {
+                self.compile_expression(value);
+                self.emit(OpCode::Throw, keyword.span.combine(value.span()));
+            }
+            Statement::Break(keyword) | Statement::Continue(keyword) => {
+                let scope_depth = self
+                    .current()
+                    .loops
+                    .last()
+                    .expect("The resolver only allows break and continue inside loops")
+                    .scope_depth;
+                let loop_count = self.current().loops.len();
+                let first_exited = self
+                    .current()
+                    .try_blocks
+                    .iter()
+                    .position(|try_block| try_block.loop_count >= loop_count)
+                    .unwrap_or(self.current().try_blocks.len());
+                self.exit_try_blocks(first_exited, keyword.span);
+                self.discard_locals(scope_depth, keyword.span);
+                let jump = self.emit(OpCode::Jump(0), keyword.span);
+                let innermost_loop = self.current().loops.last_mut().unwrap();
+                if matches!(statement, Statement::Break(_)) {
+                    innermost_loop.breaks.push(jump);
+                } else {
+                    innermost_loop.continues.push(jump);
+                }
+            }
+            Statement::Return { keyword, value } => {
+                match value {
+                    Some(value) => self.compile_expression(value),
+                    None => {
+                        self.emit(OpCode::Nil, keyword.span);
+                    }
+                }
+                // Keep the value safe in a local while any finally blocks run
+                let exits_try_blocks = !self.current().try_blocks.is_empty();
+                if exits_try_blocks {
+                    self.begin_scope();
+                    self.add_local(" return value");
+                    let slot = self.current().locals.len() - 1;
+                    self.exit_try_blocks(0, keyword.span);
+                    self.emit(OpCode::GetLocal(slot), keyword.span);
+                }
+                // Initialisers always return the instance they were called on
+                if self.current().function_type == FunctionType::Initialiser {
+                    self.emit(OpCode::Pop, keyword.span);
+                    self.emit(OpCode::GetLocal(0), keyword.span);
+                }
+                self.emit(OpCode::Return, keyword.span);
+                if exits_try_blocks {
+                    self.end_scope(keyword.span);
+                }
+            }
+        }
+    }
+
+    fn compile_block(&mut self, declarations: &[Declaration]) {
+        self.begin_scope();
+        self.compile_declarations(declarations);
+        let span = self.last_span();
+        self.end_scope(span);
+    }
+
+    /// Compile a try statement. Exceptions thrown in the try block jump to a handler that runs
+    /// the catch block, or runs the finally block and throws the exception again if there's no
+    /// catch block. Exceptions thrown in the catch block also run the finally block before
+    /// being thrown again.
+    fn compile_try(
+        &mut self,
+        span: Span,
+        body: &[Declaration],
+        catch: &Option<CatchClause>,
+        finally: &Option<Rc<Vec<Declaration>>>,
+    ) {
+        let handler = self.emit_push_handler(finally, catch.is_none(), span);
+        self.compile_block(body);
+        self.emit_pop_handler(span);
+        let skip_handler = self.emit(OpCode::Jump(0), span);
+
+        self.patch_jump(handler);
+        match (catch, finally) {
+            (Some(CatchClause { name, body }), finally) => {
+                // The catch handler starts with the thrown value on top of the stack
+                self.begin_scope();
+                self.add_local(name.span.slice(self.source));
+                match finally {
+                    Some(finally) => {
+                        let handler = self.emit_push_handler(&Some(finally.clone()), true, span);
+                        self.compile_block(body);
+                        self.emit_pop_handler(span);
+                        let skip_handler = self.emit(OpCode::Jump(0), span);
+                        self.patch_jump(handler);
+                        self.compile_rethrow(finally, span);
+                        self.patch_jump(skip_handler);
+                    }
+                    None => self.compile_block(body),
+                }
+                self.end_scope(span);
+            }
+            (None, Some(finally)) => self.compile_rethrow(finally, span),
+            (None, None) => {
+                unreachable!("The parser only allows try blocks with a catch or finally")
+            }
+        }
+        self.patch_jump(skip_handler);
+
+        if let Some(finally) = finally {
+            self.compile_block(finally);
+        }
+    }
+
+    /// Install an exception handler, returning the instruction to patch with its location.
+    /// Handlers that rethrow run a finally block rather than a catch block.
+    fn emit_push_handler(
+        &mut self,
+        finally: &Option<Rc<Vec<Declaration>>>,
+        rethrows: bool,
+        span: Span,
+    ) -> usize {
+        let loop_count = self.current().loops.len();
+        self.current().try_blocks.push(TryBlock {
+            loop_count,
+            finally: finally.clone(),
+        });
+        let op_code = if rethrows {
+            OpCode::PushFinally(0)
+        } else {
+            OpCode::PushHandler(0)
+        };
+        self.emit(op_code, span)
+    }
+
+    fn emit_pop_handler(&mut self, span: Span) {
+        self.current().try_blocks.pop();
+        self.emit(OpCode::PopHandler, span);
+    }
+
+    /// Run a finally block for an exception caught by a `PushFinally` handler, then throw it
+    /// again
+    fn compile_rethrow(&mut self, finally: &[Declaration], span: Span) {
+        self.compile_block(finally);
+        self.emit(OpCode::Rethrow, span);
+    }
+
+    /// Emit the clean up for jumping out of the try blocks from index `first` onwards:
+    /// removing their handlers and running their finally blocks, innermost first
+    fn exit_try_blocks(&mut self, first: usize, span: Span) {
+        let mut exited = vec![];
+        while self.current().try_blocks.len() > first {
+            let try_block = self.current().try_blocks.pop().unwrap();
+            self.emit(OpCode::PopHandler, span);
+            // The finally block runs with only the enclosing try blocks' handlers installed
+            if let Some(finally) = &try_block.finally {
+                self.compile_block(finally);
+            }
+            exited.push(try_block);
+        }
+        self.current().try_blocks.extend(exited.into_iter().rev());
+    }
+
+    fn compile_expression(&mut self, expression: &Rc<Expression>) {
+        let span = expression.span();
+        match &**expression {
+            Expression::Assignment(AssignmentExpression { name, value, .. }) => {
+                self.named_variable(name.span.slice(self.source), name.span, Some(value))
+            }
+            Expression::Binary(BinaryExpression {
+                left,
+                right,
+                operator,
+            }) => {
+                self.compile_expression(left);
+                self.compile_expression(right);
+                let op_codes: &[OpCode] = match operator.type_ {
+                    TokenType::Plus => &[OpCode::Add],
+                    TokenType::Minus => &[OpCode::Subtract],
+                    TokenType::Star => &[OpCode::Multiply],
+                    TokenType::Slash => &[OpCode::Divide],
+                    TokenType::EqualEqual => &[OpCode::Equal],
+                    TokenType::BangEqual => &[OpCode::Equal, OpCode::Not],
+                    TokenType::Greater => &[OpCode::Greater],
+                    TokenType::GreaterEqual => &[OpCode::Less, OpCode::Not],
+                    TokenType::Less => &[OpCode::Less],
+                    TokenType::LessEqual => &[OpCode::Greater, OpCode::Not],
+                    _ => unreachable!("{:?} is not a binary operator", operator.type_),
+                };
+                for op_code in op_codes {
+                    self.emit(*op_code, span);
+                }
+            }
+            Expression::Call(CallExpression {
+                callee, arguments, ..
+            }) => {
+                self.compile_expression(callee);
+                for argument in arguments {
+                    self.compile_expression(argument);
+                }
+                self.emit(OpCode::Call(arguments.len()), span);
+            }
+            Expression::Get(GetExpression { object, name }) => {
+                self.compile_expression(object);
+                let constant = self.identifier_constant(name.span.slice(self.source));
+                // Spans the whole expression, like the tree walking interpreter's errors
+                self.emit(OpCode::GetProperty(constant), span);
+            }
+            Expression::Function(FunctionExpression {
+                keyword,
+                parameters,
+                body,
+            }) => self.compile_function("", keyword.span, parameters, body, FunctionType::Function),
+            Expression::Grouping(GroupingExpression { expression }) => {
+                self.compile_expression(expression)
+            }
+            Expression::Index(IndexExpression { object, index, .. }) => {
+                self.compile_expression(object);
+                self.compile_expression(index);
+                self.emit(OpCode::GetIndex, index.span());
+            }
+            Expression::Interpolation(InterpolationExpression { parts }) => {
+                for part in parts {
+                    self.compile_expression(part);
+                }
+                self.emit(OpCode::Concatenate(parts.len()), span);
+            }
+            Expression::List(ListExpression { elements, .. }) => {
+                for element in elements {
+                    self.compile_expression(element);
+                }
+                self.emit(OpCode::List(elements.len()), span);
+            }
+            Expression::Map(MapExpression { entries, .. }) => {
+                for (key, value) in entries {
+                    self.compile_expression(key);
+                    self.emit(OpCode::CheckKey, key.span());
+                    self.compile_expression(value);
+                }
+                self.emit(OpCode::Map(entries.len()), span);
+            }
+            Expression::Literal(literal) => match literal {
+                LiteralExpression::String_(_, value) => {
+                    self.emit_constant(Value::String(value.as_str().into()), span);
+                }
+                LiteralExpression::Number(token) => {
+                    let number = token.span.slice(self.source).parse().unwrap_or_else(|_| {
+                        panic!(
+                            "Couldn't parse number literal {}",
+                            token.span.slice(self.source)
+                        )
+                    });
+                    self.emit_constant(Value::Number(number), span);
+                }
+                LiteralExpression::Boolean(_, true) => {
+                    self.emit(OpCode::True, span);
+                }
+                LiteralExpression::Boolean(_, false) => {
+                    self.emit(OpCode::False, span);
+                }
+                LiteralExpression::Nil(_) => {
+                    self.emit(OpCode::Nil, span);
+                }
+            },
+            Expression::Logical(LogicalExpression {
+                left,
+                right,
+                operator,
+            }) => {
+                self.compile_expression(left);
+                if operator.type_ == TokenType::And {
+                    let end_jump = self.emit(OpCode::JumpIfFalse(0), operator.span);
+                    self.emit(OpCode::Pop, operator.span);
+                    self.compile_expression(right);
+                    self.patch_jump(end_jump);
+                } else {
+                    let else_jump = self.emit(OpCode::JumpIfFalse(0), operator.span);
+                    let end_jump = self.emit(OpCode::Jump(0), operator.span);
+                    self.patch_jump(else_jump);
+                    self.emit(OpCode::Pop, operator.span);
+                    self.compile_expression(right);
+                    self.patch_jump(end_jump);
+                }
+            }
+            Expression::Set(SetExpression {
+                object,
+                name,
+                value,
+            }) => {
+                self.compile_expression(object);
+                self.compile_expression(value);
+                let constant = self.identifier_constant(name.span.slice(self.source));
+                self.emit(OpCode::SetProperty(constant), span);
+            }
+            Expression::SetIndex(SetIndexExpression {
+                object,
+                index,
+                value,
+                ..
+            }) => {
+                self.compile_expression(object);
+                self.compile_expression(index);
+                self.compile_expression(value);
+                self.emit(OpCode::SetIndex, index.span());
+            }
+            Expression::Super(SuperExpression {
+                keyword, method, ..
+            }) => {
+                self.named_variable("this", keyword.span, None);
+                self.named_variable("super", keyword.span, None);
+                let constant = self.identifier_constant(method.span.slice(self.source));
+                self.emit(OpCode::GetSuper(constant), method.span);
+            }
+            Expression::This(ThisExpression { keyword, .. }) => {
+                self.named_variable("this", keyword.span, None)
+            }
+            Expression::Unary(UnaryExpression { operator, right }) => {
+                self.compile_expression(right);
+                let op_code = match operator.type_ {
+                    TokenType::Minus => OpCode::Negate,
+                    TokenType::Bang => OpCode::Not,
+                    _ => unreachable!("{:?} is not a unary operator", operator.type_),
+                };
+                self.emit(op_code, operator.span.combine(right.span()));
+            }
+            Expression::Variable(VariableExpression { name, .. }) => {
+                self.named_variable(name.span.slice(self.source), name.span, None)
+            }
+        }
+    }
+}
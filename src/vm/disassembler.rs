@@ -0,0 +1,147 @@
+use std::fmt::Write;
+
+use crate::statement::Declaration;
+
+use super::{
+    chunk::{Chunk, OpCode},
+    compiler::Compiler,
+    value::{Function, Value},
+};
+
+/// Compile the given declarations and return a human readable listing of the bytecode for the
+/// script and every function it contains
+pub fn disassemble(source: &str, declarations: &[Declaration]) -> String {
+    let function = Compiler::compile(source, declarations);
+    let mut output = String::new();
+    disassemble_function(&function, "<script>", &mut output);
+    output
+}
+
+fn disassemble_function(function: &Function, name: &str, output: &mut String) {
+    disassemble_chunk(&function.chunk, name, output);
+
+    // Functions are stored as constants of the chunk that creates them
+    for constant in &function.chunk.constants {
+        if let Value::Function(function) = constant {
+            output.push('\n');
+            disassemble_function(function, &function.to_string(), output);
+        }
+    }
+}
+
+fn disassemble_chunk(chunk: &Chunk, name: &str, output: &mut String) {
+    writeln!(output, "== {name} ==").unwrap();
+    for offset in 0..chunk.code.len() {
+        disassemble_instruction(chunk, offset, output);
+    }
+}
+
+fn disassemble_instruction(chunk: &Chunk, offset: usize, output: &mut String) {
+    write!(output, "{offset:04} ").unwrap();
+
+    let line = chunk.lines[offset];
+    if offset > 0 && line == chunk.lines[offset - 1] {
+        write!(output, "   | ").unwrap();
+    } else {
+        write!(output, "{line:4} ").unwrap();
+    }
+
+    let constant = |index: usize| format!("{index:4} '{}'", chunk.constants[index]);
+
+    let (name, operand) = match chunk.code[offset] {
+        OpCode::Constant(index) => ("Constant", constant(index)),
+        OpCode::Nil => ("Nil", String::new()),
+        OpCode::True => ("True", String::new()),
+        OpCode::False => ("False", String::new()),
+        OpCode::Pop => ("Pop", String::new()),
+        OpCode::GetLocal(slot) => ("GetLocal", format!("{slot:4}")),
+        OpCode::SetLocal(slot) => ("SetLocal", format!("{slot:4}")),
+        OpCode::GetGlobal(index) => ("GetGlobal", constant(index)),
+        OpCode::DefineGlobal(index) => ("DefineGlobal", constant(index)),
+        OpCode::SetGlobal(index) => ("SetGlobal", constant(index)),
+        OpCode::GetUpvalue(index) => ("GetUpvalue", format!("{index:4}")),
+        OpCode::SetUpvalue(index) => ("SetUpvalue", format!("{index:4}")),
+        OpCode::GetProperty(index) => ("GetProperty", constant(index)),
+        OpCode::SetProperty(index) => ("SetProperty", constant(index)),
+        OpCode::GetSuper(index) => ("GetSuper", constant(index)),
+        OpCode::List(count) => ("List", format!("{count:4}")),
+        OpCode::CheckKey => ("CheckKey", String::new()),
+        OpCode::Map(count) => ("Map", format!("{count:4}")),
+        OpCode::GetIndex => ("GetIndex", String::new()),
+        OpCode::SetIndex => ("SetIndex", String::new()),
+        OpCode::Iterate => ("Iterate", String::new()),
+        OpCode::HasNext(slot) => ("HasNext", format!("{slot:4}")),
+        OpCode::Next(slot) => ("Next", format!("{slot:4}")),
+        OpCode::Equal => ("Equal", String::new()),
+        OpCode::Greater => ("Greater", String::new()),
+        OpCode::Less => ("Less", String::new()),
+        OpCode::Add => ("Add", String::new()),
+        OpCode::Subtract => ("Subtract", String::new()),
+        OpCode::Multiply => ("Multiply", String::new()),
+        OpCode::Divide => ("Divide", String::new()),
+        OpCode::Not => ("Not", String::new()),
+        OpCode::Negate => ("Negate", String::new()),
+        OpCode::Print => ("Print", String::new()),
+        OpCode::PushHandler(target) => ("PushHandler", format!("{offset:4} -> {target}")),
+        OpCode::PushFinally(target) => ("PushFinally", format!("{offset:4} -> {target}")),
+        OpCode::PopHandler => ("PopHandler", String::new()),
+        OpCode::Throw => ("Throw", String::new()),
+        OpCode::Rethrow => ("Rethrow", String::new()),
+        OpCode::Concatenate(count) => ("Concatenate", format!("{count:4}")),
+        OpCode::Jump(target) => ("Jump", format!("{offset:4} -> {target}")),
+        OpCode::JumpIfFalse(target) => ("JumpIfFalse", format!("{offset:4} -> {target}")),
+        OpCode::Call(argument_count) => ("Call", format!("{argument_count:4}")),
+        OpCode::Closure(index) => ("Closure", constant(index)),
+        OpCode::CloseUpvalue => ("CloseUpvalue", String::new()),
+        OpCode::Return => ("Return", String::new()),
+        OpCode::Class(index) => ("Class", constant(index)),
+        OpCode::Inherit => ("Inherit", String::new()),
+        OpCode::Method(index) => ("Method", constant(index)),
+    };
+    if operand.is_empty() {
+        writeln!(output, "{name}").unwrap();
+    } else {
+        writeln!(output, "{name:<16} {operand}").unwrap();
+    }
+
+    // Closures are followed by a description of where each upvalue is captured from
+    if let OpCode::Closure(index) = chunk.code[offset]
+        && let Value::Function(function) = &chunk.constants[index]
+    {
+        for upvalue in &function.upvalues {
+            writeln!(
+                output,
+                "{offset:04}    |   {:<16} {:4}",
+                if upvalue.is_local { "local" } else { "upvalue" },
+                upvalue.index
+            )
+            .unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer::Lexer, parser::Parser};
+
+    #[test]
+    fn disassemble_script() {
+        let source = "var a = 1;\nprint a + 2;";
+        let tokens = Lexer::lex(source).tokens;
+        let declarations = Parser::parse(&tokens).declarations;
+
+        assert_eq!(
+            disassemble(source, &declarations),
+            "== <script> ==\n\
+             0000    1 Constant            0 '1'\n\
+             0001    | DefineGlobal        1 'a'\n\
+             0002    2 GetGlobal           2 'a'\n\
+             0003    | Constant            3 '2'\n\
+             0004    | Add\n\
+             0005    | Print\n\
+             0006    | Nil\n\
+             0007    | Return\n"
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! Functions implemented in Rust that are available to every Lox program run by the virtual
+//! machine, and the methods of built in types
+
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    interpreter::{
+        error::Error,
+        native::{checked_exit_code, checked_index, checked_insert_index},
+    },
+    limits::check_size,
+    map::{Map, MapKey},
+    span::Span,
+};
+
+use super::value::{NativeMethod, NativeMethodImplementation, Value};
+
+/// The number of seconds since the Unix epoch
+pub(crate) fn clock(_arguments: &[Value], _call_span: Span) -> Result<Value, Error> {
+    Ok(Value::Number(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs_f64())
+            .unwrap_or(0.0),
+    ))
+}
+
+/// Stop the script, exiting the process with the given code if it's being run from the command
+/// line
+pub(crate) fn exit(arguments: &[Value], call_span: Span) -> Result<Value, Error> {
+    let Value::Number(code) = arguments[0] else {
+        return Err(Error::type_error(
+            "Number".into(),
+            arguments[0].string_description(),
+            call_span,
+        ));
+    };
+    Err(Error::Exit {
+        code: checked_exit_code(code, call_span)?,
+        span: call_span,
+    })
+}
+
+/// Look up a method of a built in type, bound to the given receiver
+pub(crate) fn method(receiver: &Value, name: &str) -> Option<NativeMethod> {
+    let (arity, function): (usize, NativeMethodImplementation) = match (receiver, name) {
+        (Value::List(_), "push") => (1, list_push),
+        (Value::List(_), "pop") => (0, list_pop),
+        (Value::List(_), "len") => (0, list_len),
+        (Value::List(_), "insert") => (2, list_insert),
+        (Value::List(_), "remove") => (1, list_remove),
+        (Value::Map(_), "has") => (1, map_has),
+        (Value::Map(_), "remove") => (1, map_remove),
+        (Value::Map(_), "len") => (0, map_len),
+        (Value::Map(_), "keys") => (0, map_keys),
+        (Value::Map(_), "values") => (0, map_values),
+        _ => return None,
+    };
+    Some(NativeMethod {
+        receiver: receiver.clone(),
+        arity,
+        function,
+    })
+}
+
+fn as_list(receiver: &Value) -> &RefCell<Vec<Value>> {
+    match receiver {
+        Value::List(list) => list,
+        _ => unreachable!("List methods are only looked up on lists"),
+    }
+}
+
+fn as_map(receiver: &Value) -> &RefCell<Map<Value>> {
+    match receiver {
+        Value::Map(map) => map,
+        _ => unreachable!("Map methods are only looked up on maps"),
+    }
+}
+
+fn as_key(key: &Value, span: Span) -> Result<MapKey, Error> {
+    key.to_map_key().ok_or_else(|| Error::UnhashableKey {
+        type_name: key.string_description(),
+        span,
+    })
+}
+
+fn as_index(index: &Value, length: usize, span: Span) -> Result<usize, Error> {
+    checked_index(as_index_number(index, span)?, length, span)
+}
+
+fn as_index_number(index: &Value, span: Span) -> Result<f64, Error> {
+    match index {
+        Value::Number(index) => Ok(*index),
+        _ => Err(Error::type_error(
+            "Number".into(),
+            index.string_description(),
+            span,
+        )),
+    }
+}
+
+fn list_push(
+    receiver: &Value,
+    arguments: &[Value],
+    max_size: Option<usize>,
+    call_span: Span,
+) -> Result<Value, Error> {
+    let mut list = as_list(receiver).borrow_mut();
+    check_size(max_size, list.len() + 1, call_span)?;
+    list.push(arguments[0].clone());
+    Ok(Value::Nil)
+}
+
+fn list_pop(
+    receiver: &Value,
+    _arguments: &[Value],
+    _max_size: Option<usize>,
+    call_span: Span,
+) -> Result<Value, Error> {
+    as_list(receiver)
+        .borrow_mut()
+        .pop()
+        .ok_or(Error::PopFromEmptyList(call_span))
+}
+
+fn list_len(
+    receiver: &Value,
+    _arguments: &[Value],
+    _max_size: Option<usize>,
+    _call_span: Span,
+) -> Result<Value, Error> {
+    Ok(Value::Number(as_list(receiver).borrow().len() as f64))
+}
+
+fn list_insert(
+    receiver: &Value,
+    arguments: &[Value],
+    max_size: Option<usize>,
+    call_span: Span,
+) -> Result<Value, Error> {
+    let mut list = as_list(receiver).borrow_mut();
+    let index = as_index_number(&arguments[0], call_span)?;
+    let index = checked_insert_index(index, list.len(), call_span)?;
+    check_size(max_size, list.len() + 1, call_span)?;
+    list.insert(index, arguments[1].clone());
+    Ok(Value::Nil)
+}
+
+fn list_remove(
+    receiver: &Value,
+    arguments: &[Value],
+    _max_size: Option<usize>,
+    call_span: Span,
+) -> Result<Value, Error> {
+    let mut list = as_list(receiver).borrow_mut();
+    let index = as_index(&arguments[0], list.len(), call_span)?;
+    Ok(list.remove(index))
+}
+
+fn map_has(
+    receiver: &Value,
+    arguments: &[Value],
+    _max_size: Option<usize>,
+    call_span: Span,
+) -> Result<Value, Error> {
+    let key = as_key(&arguments[0], call_span)?;
+    Ok(Value::Boolean(as_map(receiver).borrow().contains_key(&key)))
+}
+
+fn map_remove(
+    receiver: &Value,
+    arguments: &[Value],
+    _max_size: Option<usize>,
+    call_span: Span,
+) -> Result<Value, Error> {
+    let key = as_key(&arguments[0], call_span)?;
+    Ok(as_map(receiver)
+        .borrow_mut()
+        .remove(&key)
+        .unwrap_or(Value::Nil))
+}
+
+fn map_len(
+    receiver: &Value,
+    _arguments: &[Value],
+    _max_size: Option<usize>,
+    _call_span: Span,
+) -> Result<Value, Error> {
+    Ok(Value::Number(as_map(receiver).borrow().len() as f64))
+}
+
+fn map_keys(
+    receiver: &Value,
+    _arguments: &[Value],
+    _max_size: Option<usize>,
+    _call_span: Span,
+) -> Result<Value, Error> {
+    let keys = as_map(receiver)
+        .borrow()
+        .keys()
+        .map(Value::from_map_key)
+        .collect();
+    Ok(Value::List(Rc::new(RefCell::new(keys))))
+}
+
+fn map_values(
+    receiver: &Value,
+    _arguments: &[Value],
+    _max_size: Option<usize>,
+    _call_span: Span,
+) -> Result<Value, Error> {
+    let values = as_map(receiver).borrow().values().cloned().collect();
+    Ok(Value::List(Rc::new(RefCell::new(values))))
+}
@@ -0,0 +1,217 @@
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
+
+use crate::{
+    interpreter::{
+        error::{Error, ErrorValue},
+        value::{fmt_collection, fmt_quoted},
+    },
+    map::{Map, MapKey},
+    span::Span,
+};
+
+use super::chunk::Chunk;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(Rc<str>),
+    Function(Rc<Function>),
+    Closure(Rc<Closure>),
+    NativeFunction(Rc<NativeFunction>),
+    NativeMethod(Rc<NativeMethod>),
+    Class(Rc<RefCell<Class>>),
+    Instance(Rc<RefCell<Instance>>),
+    BoundMethod(Rc<BoundMethod>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<Map<Value>>>),
+    /// A runtime error caught by a `catch` block
+    Error(Rc<ErrorValue>),
+}
+
+impl Value {
+    pub(crate) fn is_falsey(&self) -> bool {
+        matches!(self, Value::Nil | Value::Boolean(false))
+    }
+
+    pub(crate) fn to_map_key(&self) -> Option<MapKey> {
+        match self {
+            Value::String(string) => Some(MapKey::String(string.to_string())),
+            Value::Number(number) => Some(MapKey::number(*number)),
+            Value::Boolean(boolean) => Some(MapKey::Boolean(*boolean)),
+            Value::Nil => Some(MapKey::Nil),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn from_map_key(key: &MapKey) -> Value {
+        match key {
+            MapKey::String(string) => Value::String(string.as_str().into()),
+            MapKey::Number(bits) => Value::Number(f64::from_bits(*bits)),
+            MapKey::Boolean(boolean) => Value::Boolean(*boolean),
+            MapKey::Nil => Value::Nil,
+        }
+    }
+
+    pub(crate) fn string_description(&self) -> String {
+        match self {
+            Value::Nil => "Nil".into(),
+            Value::Boolean(_) => "Boolean".into(),
+            Value::Number(_) => "Number".into(),
+            Value::String(_) => "String".into(),
+            Value::Function(_)
+            | Value::Closure(_)
+            | Value::NativeFunction(_)
+            | Value::NativeMethod(_)
+            | Value::BoundMethod(_) => "Callable".into(),
+            Value::Class(_) => "Class".into(),
+            Value::Instance(_) => "Instance".into(),
+            Value::List(_) => "List".into(),
+            Value::Map(_) => "Map".into(),
+            Value::Error(_) => "Error".into(),
+        }
+    }
+
+    pub(crate) fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Boolean(left), Value::Boolean(right)) => left == right,
+            (Value::Number(left), Value::Number(right)) => left == right,
+            (Value::String(left), Value::String(right)) => left == right,
+            (Value::Function(left), Value::Function(right)) => Rc::ptr_eq(left, right),
+            (Value::Closure(left), Value::Closure(right)) => Rc::ptr_eq(left, right),
+            (Value::NativeFunction(left), Value::NativeFunction(right)) => Rc::ptr_eq(left, right),
+            (Value::NativeMethod(left), Value::NativeMethod(right)) => Rc::ptr_eq(left, right),
+            (Value::Class(left), Value::Class(right)) => Rc::ptr_eq(left, right),
+            (Value::Instance(left), Value::Instance(right)) => Rc::ptr_eq(left, right),
+            (Value::BoundMethod(left), Value::BoundMethod(right)) => Rc::ptr_eq(left, right),
+            (Value::List(left), Value::List(right)) => Rc::ptr_eq(left, right),
+            (Value::Map(left), Value::Map(right)) => Rc::ptr_eq(left, right),
+            (Value::Error(left), Value::Error(right)) => Rc::ptr_eq(left, right),
+            _ => false,
+        }
+    }
+}
+
+/// Strings are printed as they are, unless the alternate flag (`{:#}`) is given, which quotes
+/// them. Lists and maps print their elements that way so they can't be confused with other values.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Boolean(boolean) => write!(f, "{boolean}"),
+            Value::Number(number) => write!(f, "{number}"),
+            Value::String(string) if f.alternate() => fmt_quoted(string, f),
+            Value::String(string) => write!(f, "{string}"),
+            Value::Function(function) => write!(f, "{function}"),
+            Value::Closure(closure) => write!(f, "{}", closure.function),
+            Value::NativeFunction(_) | Value::NativeMethod(_) => write!(f, "<native fn>"),
+            Value::Class(class) => write!(f, "{}", class.borrow().name),
+            Value::Instance(instance) => {
+                write!(f, "{} instance", instance.borrow().class.borrow().name)
+            }
+            Value::BoundMethod(bound_method) => write!(f, "{}", bound_method.method.function),
+            Value::List(list) => fmt_collection(Rc::as_ptr(list).cast(), "[...]", f, |f| {
+                write!(f, "[")?;
+                for (index, element) in list.borrow().iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{element:#}")?;
+                }
+                write!(f, "]")
+            }),
+            Value::Map(map) => fmt_collection(Rc::as_ptr(map).cast(), "{...}", f, |f| {
+                write!(f, "{}", map.borrow())
+            }),
+            Value::Error(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Function {
+    /// Empty for the top level script and anonymous functions
+    pub name: String,
+    /// Where the function was declared. `None` for the top level script.
+    pub name_span: Option<Span>,
+    pub arity: usize,
+    /// Where each of the closure's upvalues should be captured from when it's created
+    pub upvalues: Vec<UpvalueDescriptor>,
+    pub chunk: Chunk,
+    /// The source the function was compiled from, which its chunk's spans point into
+    pub source: Rc<str>,
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Anonymous functions and the top level script don't have names
+        if self.name.is_empty() {
+            write!(f, "<fn>")
+        } else {
+            write!(f, "<fn {}>", self.name)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpvalueDescriptor {
+    /// Whether the upvalue captures a local of the immediately enclosing function, or one of
+    /// its upvalues
+    pub is_local: bool,
+    pub index: usize,
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+#[derive(Debug)]
+pub enum Upvalue {
+    /// The captured variable still lives on the stack, in the given slot
+    Open(usize),
+    /// The captured variable has gone out of scope, so the upvalue owns it
+    Closed(Value),
+}
+
+/// A function implemented in Rust that can be called from Lox. Receives the arguments and the
+/// span of the call
+#[derive(Debug)]
+pub struct NativeFunction {
+    pub arity: usize,
+    pub function: fn(&[Value], Span) -> Result<Value, Error>,
+}
+
+/// The implementation of a method of a built in type. It's given the receiver, the arguments,
+/// the maximum size a collection may grow to, and the span of the call expression.
+pub type NativeMethodImplementation =
+    fn(&Value, &[Value], Option<usize>, Span) -> Result<Value, Error>;
+
+/// A method of a built in type implemented in Rust, bound to the value it was accessed on
+#[derive(Debug)]
+pub struct NativeMethod {
+    pub receiver: Value,
+    pub arity: usize,
+    pub function: NativeMethodImplementation,
+}
+
+#[derive(Debug)]
+pub struct Class {
+    pub name: String,
+    pub methods: HashMap<String, Rc<Closure>>,
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub class: Rc<RefCell<Class>>,
+    pub fields: HashMap<String, Value>,
+}
+
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: Rc<Closure>,
+}
@@ -0,0 +1,22 @@
+//! Helpers shared by the integration tests. Each test file only uses some of them.
+#![allow(dead_code)]
+
+#[path = "../../src/golden.rs"]
+pub mod golden;
+
+use std::io;
+
+use rusty_lox::{Error, Vm};
+
+/// Run `source` with both backends, which should both fail at runtime, returning the source
+/// each error points at
+pub fn runtime_error_spans(source: &str) -> [String; 2] {
+    let Err(Error::Runtime(tree_walker_error)) = rusty_lox::run(source) else {
+        panic!("The tree walking interpreter should fail");
+    };
+    let declarations = rusty_lox::parse(source).unwrap();
+    let vm_error = Vm::with_output(io::sink())
+        .interpret(source, &declarations)
+        .unwrap_err();
+    [tree_walker_error, vm_error].map(|error| error.span().slice(source).to_string())
+}
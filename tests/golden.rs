@@ -0,0 +1,38 @@
+mod common;
+
+use std::{fs, path::Path};
+
+use common::golden::{self, Backend};
+
+/// Run every script in `directory` with both backends, failing with every mismatch found
+fn check_directory(directory: &str) {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join(directory);
+    let scripts = golden::find_scripts(&directory).unwrap();
+    assert!(!scripts.is_empty());
+
+    let mut failures = vec![];
+    for script in scripts {
+        let source = fs::read_to_string(&script).unwrap();
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            if let Err(errors) = golden::check(&source, backend) {
+                failures.push(format!(
+                    "{} ({backend:?}):\n    {}",
+                    script.display(),
+                    errors.join("\n    ")
+                ));
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn language_tests() {
+    check_directory("tests/lox");
+}
+
+#[test]
+fn examples() {
+    check_directory("examples");
+}
@@ -0,0 +1,451 @@
+mod common;
+
+use std::{fs::File, io, time::Duration};
+
+use common::{golden::SharedBuffer, runtime_error_spans};
+use rusty_lox::{CancellationHandle, Color, Error, Interpreter, Limits, SourceMap, Value, Vm};
+
+#[test]
+fn interpreter_keeps_globals_between_runs() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .run("var count = 1; fun increment() { count = count + 1; }")
+        .unwrap();
+    interpreter.run("increment(); increment();").unwrap();
+
+    let count = interpreter.get_global("count").unwrap();
+    assert!(matches!(*count, Value::Number(_, number) if number == 3.0));
+    assert!(interpreter
+        .global_names()
+        .contains(&"increment".to_string()));
+    assert!(interpreter.get_global("missing").is_none());
+}
+
+#[test]
+fn errors_are_returned_as_values() {
+    assert!(matches!(
+        rusty_lox::run("var a = \"unterminated;"),
+        Err(Error::Syntax(_))
+    ));
+    assert!(matches!(rusty_lox::run("var = 1;"), Err(Error::Syntax(_))));
+    assert!(matches!(
+        rusty_lox::run("return 1;"),
+        Err(Error::Resolver(_))
+    ));
+    assert!(matches!(
+        rusty_lox::run("print -nil;"),
+        Err(Error::Runtime(_))
+    ));
+}
+
+#[test]
+fn print_output_can_be_captured() {
+    let source =
+        "fun greet(name) { return \"hello \" + name; }\nprint greet(\"lox\");\nprint 1 + 2;";
+    let output = SharedBuffer::default();
+    Interpreter::with_output(output.clone())
+        .run(source)
+        .unwrap();
+    assert_eq!(output.contents(), "hello lox\n3\n");
+
+    let output = SharedBuffer::default();
+    let declarations = rusty_lox::parse(source).unwrap();
+    Vm::with_output(output.clone())
+        .interpret(source, &declarations)
+        .unwrap();
+    assert_eq!(output.contents(), "hello lox\n3\n");
+}
+
+#[test]
+fn errors_can_be_rendered_into_a_writer() {
+    let source = "print -nil;";
+    let error = rusty_lox::run(source).unwrap_err();
+    let mut output = vec![];
+    error.display(source, &mut output).unwrap();
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .contains("Type Error: expected Number, got Nil"));
+}
+
+#[test]
+fn errors_written_to_a_buffer_or_file_are_not_coloured() {
+    let source = "print -nil;";
+    let error = rusty_lox::run(source).unwrap_err();
+    let mut output = vec![];
+    error.display(source, &mut output).unwrap();
+    assert!(!String::from_utf8(output).unwrap().contains("\x1b["));
+
+    let file = File::open(file!()).unwrap();
+    assert_eq!(Color::auto(&file), Color::Never);
+}
+
+#[test]
+fn runtime_errors_inside_functions_have_stack_traces() {
+    let source = "fun inner() {\n  return -nil;\n}\nfun outer() {\n  inner();\n}\nouter();";
+    let expected =
+        "  in inner at line 2, called from line 5\n  in outer at line 5, called from line 7\n";
+
+    let error = rusty_lox::run(source).unwrap_err();
+    let mut output = vec![];
+    error.display(source, &mut output).unwrap();
+    assert!(String::from_utf8(output).unwrap().contains(expected));
+
+    let declarations = rusty_lox::parse(source).unwrap();
+    let error = Vm::new().interpret(source, &declarations).unwrap_err();
+    let mut output = vec![];
+    error.display(source, &mut output).unwrap();
+    assert!(String::from_utf8(output).unwrap().contains(expected));
+}
+
+#[test]
+fn max_call_depth_is_configurable() {
+    let source = "fun count(n) { if (n == 0) return 0; return count(n - 1) + 1; }\nprint count(5);";
+
+    let mut interpreter = Interpreter::with_output(io::sink());
+    interpreter.set_max_call_depth(3);
+    let error = interpreter.run(source).unwrap_err();
+    assert!(matches!(&error, Error::Runtime(error) if error.message(source) == "Stack overflow"));
+    interpreter.set_max_call_depth(10);
+    interpreter.run(source).unwrap();
+
+    let declarations = rusty_lox::parse(source).unwrap();
+    let mut vm = Vm::with_output(io::sink());
+    vm.set_max_call_depth(3);
+    let error = vm.interpret(source, &declarations).unwrap_err();
+    assert_eq!(error.message(source), "Stack overflow");
+}
+
+/// Run a script with both backends under the given limits, returning their error messages
+fn run_limited(source: &str, limits: Limits) -> [String; 2] {
+    let mut interpreter = Interpreter::with_output(io::sink());
+    interpreter.set_limits(limits.clone());
+    let Err(Error::Runtime(tree_walker_error)) = interpreter.run(source) else {
+        panic!("The tree walking interpreter should hit a limit");
+    };
+
+    let declarations = rusty_lox::parse(source).unwrap();
+    let mut vm = Vm::with_output(io::sink());
+    vm.set_limits(limits);
+    let vm_error = vm.interpret(source, &declarations).unwrap_err();
+
+    [tree_walker_error.message(source), vm_error.message(source)]
+}
+
+#[test]
+fn limits_stop_runaway_scripts() {
+    // Limits can't be caught
+    let infinite_loop = "try { while (true) {} } catch (e) { print e; }";
+    let limits = Limits {
+        max_steps: Some(1000),
+        ..Limits::default()
+    };
+    assert_eq!(
+        run_limited(infinite_loop, limits),
+        ["Step limit of 1000 exceeded"; 2]
+    );
+
+    let limits = Limits {
+        timeout: Some(Duration::from_millis(10)),
+        ..Limits::default()
+    };
+    assert_eq!(
+        run_limited(infinite_loop, limits),
+        ["Timed out after 10ms"; 2]
+    );
+
+    let cancellation = CancellationHandle::new();
+    cancellation.cancel();
+    let limits = Limits {
+        cancellation: Some(cancellation),
+        ..Limits::default()
+    };
+    assert_eq!(run_limited(infinite_loop, limits), ["Cancelled"; 2]);
+
+    let limits = Limits {
+        max_collection_size: Some(100),
+        ..Limits::default()
+    };
+    assert_eq!(
+        run_limited("var list = []; while (true) list.push(1);", limits.clone()),
+        ["Collection size limit of 100 exceeded"; 2]
+    );
+    assert_eq!(
+        run_limited("var s = \"s\"; while (true) s = s + s;", limits),
+        ["Collection size limit of 100 exceeded"; 2]
+    );
+}
+
+#[test]
+fn lists_never_grow_past_the_collection_size_limit() {
+    let limits = Limits {
+        max_collection_size: Some(2),
+        ..Limits::default()
+    };
+    let setup = "var list = [1, 2];";
+    let grow = "list.push(3);";
+    let check = "print list.len();";
+
+    let output = SharedBuffer::default();
+    let mut interpreter = Interpreter::with_output(output.clone());
+    interpreter.set_limits(limits.clone());
+    interpreter.run(setup).unwrap();
+    interpreter.run(grow).unwrap_err();
+    interpreter.run(check).unwrap();
+    assert_eq!(output.contents(), "2\n");
+
+    let output = SharedBuffer::default();
+    let mut vm = Vm::with_output(output.clone());
+    vm.set_limits(limits);
+    for source in [setup, grow, check] {
+        let declarations = rusty_lox::parse(source).unwrap();
+        let _ = vm.interpret(source, &declarations);
+    }
+    assert_eq!(output.contents(), "2\n");
+}
+
+#[test]
+fn errors_are_prefixed_with_their_location() {
+    let source = "var s = \"é\";\nprint \"ü\" == \"ü\" and -nil;";
+    let error = rusty_lox::run(source).unwrap_err();
+    let mut output = vec![];
+    let source_map = SourceMap::with_file_name("script.lox", source);
+    error
+        .display_with(&source_map, Color::Always, &mut output)
+        .unwrap();
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .contains("script.lox:2:22: \x1b[31mError:\x1b[0m Type Error"));
+}
+
+#[test]
+fn arity_errors_point_at_the_declaration() {
+    let source = "fun add(a, b) {\n  return a + b;\n}\nadd(1);";
+    let error = rusty_lox::run(source).unwrap_err();
+    let mut output = vec![];
+    error
+        .display_with(&SourceMap::new(source), Color::Never, &mut output)
+        .unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains(
+        " 1 |  fun add(a, b) {\n   |      --- function declared here with 2 parameters\n"
+    ));
+    assert!(!output.contains('\x1b'));
+}
+
+#[test]
+fn errors_convert_to_diagnostics() {
+    let codes = |source: &str| {
+        rusty_lox::run(source)
+            .unwrap_err()
+            .diagnostics(source)
+            .iter()
+            .map(|diagnostic| (diagnostic.code, diagnostic.span.slice(source).to_string()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(codes("print \"\\q\";"), [("LEX003", "\\q".into())]);
+    assert_eq!(codes("var = 1;"), [("PARSE001", "=".into())]);
+    assert_eq!(codes("break;"), [("RESOLVE007", "break".into())]);
+    assert_eq!(
+        codes("fun f() { return -nil; }\nf();"),
+        [("RUNTIME001", "-nil".into())]
+    );
+}
+
+#[test]
+fn undefined_variables_suggest_similar_names() {
+    let suggestions = |source: &str| {
+        let Err(Error::Runtime(tree_walker_error)) = rusty_lox::run(source) else {
+            panic!("The tree walking interpreter should fail");
+        };
+        let declarations = rusty_lox::parse(source).unwrap();
+        let vm_error = Vm::with_output(io::sink())
+            .interpret(source, &declarations)
+            .unwrap_err();
+        [tree_walker_error, vm_error].map(|error| error.diagnostic(source).help.concat())
+    };
+
+    assert_eq!(
+        suggestions("var counter = 1;\nprint conter;"),
+        ["did you mean `counter`?"; 2]
+    );
+    assert_eq!(
+        suggestions("fun greet(name) {}\n{ var message = 1; greet(mesage); }"),
+        ["did you mean `message`?"; 2]
+    );
+    assert_eq!(
+        suggestions(
+            "fun make() {\n  var total = 0;\n  fun add() { totl = 1; }\n  add();\n}\nmake();"
+        ),
+        ["did you mean `total`?"; 2]
+    );
+    assert_eq!(
+        suggestions("fun greet() {}\ngreat();"),
+        ["did you mean `greet`?"; 2]
+    );
+    assert_eq!(suggestions("print nothing;"), [""; 2]);
+}
+
+#[test]
+fn syntax_errors_are_reported_together_in_source_order() {
+    let source = "print 1 # 2;\nvar = 3;\nvar a = \"é\" @;\nprint \"never closed;";
+    let error = rusty_lox::run(source).unwrap_err();
+    let codes = error
+        .diagnostics(source)
+        .iter()
+        .map(|diagnostic| (diagnostic.code, diagnostic.span.slice(source).to_string()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        codes,
+        [
+            ("LEX002", "#".into()),
+            ("PARSE001", "=".into()),
+            ("LEX002", "@".into()),
+            ("LEX001", "\"never closed;".into()),
+        ]
+    );
+
+    let mut output = vec![];
+    error
+        .display_with(&SourceMap::new(source), Color::Never, &mut output)
+        .unwrap();
+    assert!(String::from_utf8(output).unwrap().ends_with("\n4 errors\n"));
+}
+
+#[test]
+fn exit_stops_the_script_with_its_code() {
+    let source = "try { exit(3); } catch (e) { print \"caught\"; }\nprint \"after\";";
+
+    let output = SharedBuffer::default();
+    let error = Interpreter::with_output(output.clone())
+        .run(source)
+        .unwrap_err();
+    assert_eq!(error.exit_code(), Some(3));
+    assert_eq!(output.contents(), "");
+
+    let declarations = rusty_lox::parse(source).unwrap();
+    let output = SharedBuffer::default();
+    let error = Vm::with_output(output.clone())
+        .interpret(source, &declarations)
+        .unwrap_err();
+    assert_eq!(error.exit_code(), Some(3));
+    assert_eq!(output.contents(), "");
+
+    assert_eq!(rusty_lox::run("print -nil;").unwrap_err().exit_code(), None);
+}
+
+#[test]
+fn deep_recursion_fails_cleanly_on_a_normal_thread() {
+    // Test threads only get a couple of megabytes of stack, far less than the default call
+    // depth needs in a debug build
+    let source =
+        "fun count(n) { if (n == 0) return 0; return count(n - 1) + 1; }\nprint count(100000);";
+
+    let error = Interpreter::with_output(io::sink())
+        .run(source)
+        .unwrap_err();
+    assert!(matches!(&error, Error::Runtime(error) if error.message(source) == "Stack overflow"));
+
+    let declarations = rusty_lox::parse(source).unwrap();
+    let error = Vm::with_output(io::sink())
+        .interpret(source, &declarations)
+        .unwrap_err();
+    assert_eq!(error.message(source), "Stack overflow");
+}
+
+#[test]
+fn both_backends_report_type_errors_at_the_operator_expression() {
+    assert_eq!(
+        runtime_error_spans("var x = nil;\nprint x + 1;"),
+        ["x + 1"; 2]
+    );
+    assert_eq!(
+        runtime_error_spans("fun g() { return 1 + nil; }\ng();"),
+        ["1 + nil"; 2]
+    );
+    assert_eq!(
+        runtime_error_spans("var s = \"a\";\nprint s + true;"),
+        ["s + true"; 2]
+    );
+    assert_eq!(runtime_error_spans("var x = nil;\nprint -x;"), ["-x"; 2]);
+    assert_eq!(
+        runtime_error_spans("var x = \"a\";\nprint x < 1;"),
+        ["x < 1"; 2]
+    );
+    assert_eq!(runtime_error_spans("var x = 1;\nprint x.y;"), ["x.y"; 2]);
+    assert_eq!(runtime_error_spans("var x = 1;\nx.y = 2;"), ["x.y"; 2]);
+    assert_eq!(
+        runtime_error_spans("class A {}\nprint A().missing;"),
+        ["missing"; 2]
+    );
+}
+
+#[test]
+fn errors_involving_native_functions_point_at_where_they_are_used() {
+    let span = |source: &str| {
+        let Err(Error::Runtime(error)) = rusty_lox::run(source) else {
+            panic!("The script should fail");
+        };
+        error.span().slice(source).to_string()
+    };
+    assert_eq!(span("print 1;\nprint clock[0];"), "clock");
+    assert_eq!(span("print 1;\nfor (var x in clock) {}"), "clock");
+    assert_eq!(span("var l = [];\nprint l.push[0];"), "l.push");
+}
+
+#[test]
+fn both_backends_report_unhashable_keys_at_the_same_span() {
+    assert_eq!(runtime_error_spans("var m = {};\nm[[]] = 1;"), ["[]"; 2]);
+    assert_eq!(runtime_error_spans("var m = {};\nprint m[[]];"), ["[]"; 2]);
+    assert_eq!(runtime_error_spans("var m = {1: 2, []: 1};"), ["[]"; 2]);
+}
+
+#[test]
+fn errors_in_functions_from_earlier_runs_are_shown_in_their_own_source() {
+    let declaration = "var unused = 1;\nfun get(object) {\n  return object.missing;\n}";
+    let source = "class A {}\nget(A());";
+    let check = |error: Error| {
+        let Error::Runtime(runtime_error) = &error else {
+            panic!("The call should fail at runtime");
+        };
+        assert_eq!(
+            runtime_error.message(source),
+            "Undefined property 'missing'"
+        );
+        let mut output = vec![];
+        error
+            .display_with(&SourceMap::new(source), Color::Never, &mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("3 |    return object.missing;"));
+        assert!(output.contains("in get at line 3, called from line 2"));
+    };
+
+    let mut interpreter = Interpreter::with_output(io::sink());
+    interpreter.run(declaration).unwrap();
+    check(interpreter.run(source).unwrap_err());
+
+    let mut vm = Vm::with_output(io::sink());
+    let declarations = rusty_lox::parse(declaration).unwrap();
+    vm.interpret(declaration, &declarations).unwrap();
+    let declarations = rusty_lox::parse(source).unwrap();
+    check(Error::Runtime(
+        vm.interpret(source, &declarations).unwrap_err(),
+    ));
+}
+
+#[test]
+fn rethrown_errors_keep_the_calls_they_escaped() {
+    let source = "fun g() {\n  return 1 + nil;\n}\nfun f() {\n  try {\n    g();\n  } catch (e) {\n    throw e;\n  }\n}\nf();";
+    let expected = "  in g at line 2, called from line 6\n  in f at line 6, called from line 11\n";
+
+    let error = rusty_lox::run(source).unwrap_err();
+    let mut output = vec![];
+    error.display(source, &mut output).unwrap();
+    assert!(String::from_utf8(output).unwrap().contains(expected));
+
+    let declarations = rusty_lox::parse(source).unwrap();
+    let error = Vm::new().interpret(source, &declarations).unwrap_err();
+    let mut output = vec![];
+    error.display(source, &mut output).unwrap();
+    assert!(String::from_utf8(output).unwrap().contains(expected));
+}